@@ -1,29 +1,97 @@
 use crate::{
     command::Command,
+    complete::{
+        self,
+        CompletionSpec
+    },
     environment::{
+        Abbreviation,
         Environment, 
-        Identifier::Alias
+        Identifier::{
+            Alias,
+            Function
+        }
     },
     shell::{
         self, 
         Error
+    },
+    evaluate::{
+        evaluate,
+        process_command,
+        spawn_process
+    },
+    history::{
+        self,
+        HistoryEntry
+    },
+    job,
+    signal,
+    redirect,
+    resource::{
+        self,
+        Limit,
+        LIMITS
     }
 };
 
-use std::slice::Iter;
+use std::{
+    env::{
+        self,
+        current_exe
+    },
+    ffi::CString,
+    io::{
+        self,
+        Write
+    },
+    os::unix::ffi::OsStrExt,
+    process,
+    slice::Iter,
+    time::{
+        Duration,
+        Instant
+    }
+};
 
 use phf::phf_map;
 
 type EvalFn = fn(&Command, &mut Iter<Command>, &mut Environment) -> Result<i32, Error>;
 
 pub static BUILTINS: phf::Map<&'static str, EvalFn> = phf_map! {
-    "exit"    => Command::eval_exit,
-    "echo"    => Command::eval_echo,
-    "alias"   => Command::eval_alias,
-    "export"  => Command::eval_export,
-    "require" => Command::eval_require,
-    "cd"      => Command::eval_cd,
-    "if"      => Command::eval_if,
+    "exit"     => Command::eval_exit,
+    "echo"     => Command::eval_echo,
+    "alias"    => Command::eval_alias,
+    "unalias"  => Command::eval_unalias,
+    "abbr"     => Command::eval_abbr,
+    "export"   => Command::eval_export,
+    "const"    => Command::eval_const,
+    "readonly" => Command::eval_readonly,
+    "unset"    => Command::eval_unset,
+    "set"      => Command::eval_set,
+    "require"  => Command::eval_require,
+    "cd"       => Command::eval_cd,
+    "if"       => Command::eval_if,
+    "while"    => Command::eval_while,
+    "def"      => Command::eval_def,
+    "type"     => Command::eval_type,
+    "which"    => Command::eval_which,
+    "rehash"   => Command::eval_rehash,
+    "jobs"     => Command::eval_jobs,
+    "fg"       => Command::eval_fg,
+    "bg"       => Command::eval_bg,
+    "wait"     => Command::eval_wait,
+    "trap"     => Command::eval_trap,
+    "kill"     => Command::eval_kill,
+    "time"     => Command::eval_time,
+    "ulimit"   => Command::eval_ulimit,
+    "umask"    => Command::eval_umask,
+    "nice"     => Command::eval_nice,
+    "exec"     => Command::eval_exec,
+    "timeout"  => Command::eval_timeout,
+    "history"  => Command::eval_history,
+    "complete" => Command::eval_complete,
+    "status"   => Command::eval_status,
 };
 
 impl Command {
@@ -31,7 +99,7 @@ impl Command {
         match self.args.len() {
             0 => Err(Error::EarlyExit(0)),
             1 => {
-                let str = self.args.get(0).unwrap().eval(env)?;
+                let str = self.args.first().unwrap().eval(env)?;
                 let arg = str.parse::<i32>();
                 if let Ok(exit_code) = arg {
                     Err(Error::EarlyExit(exit_code))
@@ -56,25 +124,149 @@ impl Command {
             }
         }
 
-        println!("");
+        println!();
 
         Ok(0)
     }
 
     fn eval_alias(&self, _commands: &mut Iter<Command>, env: &mut Environment) -> Result<i32, Error> {
-        if self.args.len() < 3 || &*self.args.get(1).unwrap().eval(env)? != "=" {
+        if self.args.is_empty() {
+            for (name, substitute) in env.aliases() {
+                println!("alias {} = {}", name, substitute);
+            }
+            Ok(0)
+        }
+        else if self.args.len() < 3 || &*self.args.get(1).unwrap().eval(env)? != "=" {
             eprintln!("alias: expect matching arguments: alias <name> = <value...>");
             Ok(2)
         }
         else { 
             let alias = Alias { substitute: Command::from_args(&self.args[2..], env)? };
-            let name = self.args.get(0).unwrap().eval(env)?;
+            let name = self.args.first().unwrap().eval(env)?;
             env.add_ident(name, alias);
 
             Ok(0)
         }
     }
 
+    fn eval_unalias(&self, _commands: &mut Iter<Command>, env: &mut Environment) -> Result<i32, Error> {
+        if self.args.is_empty() {
+            eprintln!("unalias: expect arguments matching: unalias <name...>");
+            return Ok(2);
+        }
+
+        let mut exit_code = 0;
+        for arg in &self.args {
+            let name = arg.eval(env)?;
+            if !env.remove_alias(&name) {
+                eprintln!("unalias: {}: not found", name);
+                exit_code = 1;
+            }
+        }
+
+        Ok(exit_code)
+    }
+
+    fn eval_abbr(&self, _commands: &mut Iter<Command>, env: &mut Environment) -> Result<i32, Error> {
+        let mut args = Vec::new();
+        for arg in &self.args {
+            args.push(arg.eval(env)?);
+        }
+
+        let mut abbreviation = Abbreviation {
+            name: String::new(),
+            expansion: String::new(),
+            function: false,
+            anywhere: false,
+            set_cursor: None
+        };
+        let (mut erase, mut list) = (false, false);
+        let mut words = Vec::new();
+        let mut args = args.into_iter().peekable();
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-a" | "--add" => (),
+                "-e" | "--erase" => erase = true,
+                "-l" | "--list" => list = true,
+                "-s" | "--show" => (),
+                "-p" | "--position" => match args.next().as_deref() {
+                    Some("command") => abbreviation.anywhere = false,
+                    Some("anywhere") => abbreviation.anywhere = true,
+                    _ => {
+                        eprintln!("abbr: `{}` expects `command` or `anywhere`", arg);
+                        return Ok(2);
+                    }
+                },
+                "-f" | "--function" => match args.next() {
+                    Some(function) => {
+                        abbreviation.function = true;
+                        abbreviation.expansion = function;
+                    }
+                    None => {
+                        eprintln!("abbr: `{}` expects a function", arg);
+                        return Ok(2);
+                    }
+                },
+                "--set-cursor" => {
+                    // `--set-cursor=MARKER` arrives as three words, `=` being a token of its own
+                    let marker = if args.peek().is_some_and(|arg| arg == "=") {
+                        args.nth(1)
+                    } else {
+                        None
+                    };
+                    abbreviation.set_cursor = Some(marker.unwrap_or_else(|| String::from("%")));
+                }
+                "--" => {
+                    words.extend(args.by_ref());
+                }
+                _ if arg.starts_with("--set-cursor=") => {
+                    abbreviation.set_cursor = Some(arg["--set-cursor=".len()..].to_string());
+                }
+                _ if arg.starts_with('-') && arg.len() > 1 => {
+                    eprintln!("abbr: unknown option `{}`", arg);
+                    return Ok(2);
+                }
+                _ => words.push(arg)
+            }
+        }
+
+        if erase {
+            let mut exit_code = 0;
+            for name in &words {
+                if !env.remove_abbreviation(name) {
+                    eprintln!("abbr: {}: not found", name);
+                    exit_code = 1;
+                }
+            }
+            return Ok(exit_code);
+        }
+
+        if words.is_empty() && !abbreviation.function {
+            for abbreviation in env.abbreviations() {
+                if list {
+                    println!("{}", abbreviation.name);
+                } else {
+                    println!("{}", abbreviation);
+                }
+            }
+            return Ok(0);
+        }
+
+        let expected = if abbreviation.function { 1 } else { 2 };
+        if words.len() < expected || (abbreviation.function && words.len() > 1) || words[0].contains(char::is_whitespace) {
+            eprintln!("abbr: usage: abbr [-a] [-p command|anywhere] [--set-cursor[=MARKER]] [-f FUNCTION] NAME [EXPANSION...]");
+            return Ok(2);
+        }
+
+        abbreviation.name = words.remove(0);
+        if !abbreviation.function {
+            abbreviation.expansion = words.join(" ");
+        }
+        env.add_abbreviation(abbreviation);
+        Ok(0)
+    }
+
     fn eval_export(&self, _commands: &mut Iter<Command>, env: &mut Environment) -> Result<i32, Error> {
         if self.args.len() != 3 || &*self.args.get(1).unwrap().eval(env)? != "=" {
             eprintln!("export: expect matching arguments: export <name> = <value...>");
@@ -82,20 +274,130 @@ impl Command {
         }
         else {
             let value = self.args.get(2).unwrap().eval(env)?;
-            let name = self.args.get(0).unwrap().eval(env)?;
-            env.add_var(name, value);
+            let name = self.args.first().unwrap().eval(env)?;
+            env.export_var(name, value)?;
 
             Ok(0)
         }
     }
 
+    fn eval_const(&self, _commands: &mut Iter<Command>, env: &mut Environment) -> Result<i32, Error> {
+        if self.args.len() != 3 || &*self.args.get(1).unwrap().eval(env)? != "=" {
+            eprintln!("const: expect matching arguments: const <name> = <value>");
+            Ok(2)
+        }
+        else {
+            let value = self.args.get(2).unwrap().eval(env)?;
+            let name = self.args.first().unwrap().eval(env)?;
+            env.add_const(name, value)?;
+
+            Ok(0)
+        }
+    }
+
+    fn eval_readonly(&self, _commands: &mut Iter<Command>, env: &mut Environment) -> Result<i32, Error> {
+        match self.args.len() {
+            0 => {
+                for (name, var) in env.read_only_vars() {
+                    println!("readonly {} = \"{}\"", name, var.value());
+                }
+                Ok(0)
+            }
+            1 => {
+                let name = self.args.first().unwrap().eval(env)?;
+                env.make_read_only(&name);
+                Ok(0)
+            }
+            3 if &*self.args.get(1).unwrap().eval(env)? == "=" => {
+                let value = self.args.get(2).unwrap().eval(env)?;
+                let name = self.args.first().unwrap().eval(env)?;
+                env.add_const(name, value)?;
+                Ok(0)
+            }
+            _ => {
+                eprintln!("readonly: expect matching arguments: readonly <name> [= <value>]");
+                Ok(2)
+            }
+        }
+    }
+
+    fn eval_unset(&self, _commands: &mut Iter<Command>, env: &mut Environment) -> Result<i32, Error> {
+        if self.args.is_empty() {
+            eprintln!("unset: expect arguments matching: unset <name...>");
+            return Ok(2);
+        }
+
+        let mut exit_code = 0;
+        for arg in &self.args {
+            let name = arg.eval(env)?;
+            if !env.remove_var(&name)? {
+                exit_code = 1;
+            }
+        }
+
+        Ok(exit_code)
+    }
+
+    fn eval_set(&self, _commands: &mut Iter<Command>, env: &mut Environment) -> Result<i32, Error> {
+        let mut args = Vec::new();
+        for arg in &self.args {
+            args.push(arg.eval(env)?);
+        }
+
+        let mut universal = false;
+        let mut erase = false;
+        while let Some(flag) = args.first().filter(|arg| arg.starts_with('-')) {
+            for c in flag.chars().skip(1) {
+                match c {
+                    'U' => universal = true,
+                    'e' => erase = true,
+                    _ => {
+                        eprintln!("set: unknown option `-{}`", c);
+                        return Ok(2);
+                    }
+                }
+            }
+            args.remove(0);
+        }
+
+        if args.is_empty() {
+            if erase || universal {
+                eprintln!("set: expect arguments matching: set [-U] [-e] <name> [<value...>]");
+                return Ok(2);
+            }
+
+            for (name, var) in env.variables() {
+                println!("{} {}", name, var.value());
+            }
+            return Ok(0);
+        }
+
+        let name = args.remove(0);
+        let value = args.join(" ");
+
+        let result = match (erase, universal) {
+            (true, true) => env.remove_universal(&name)?.map(|_| ()),
+            (true, false) => return Ok(if env.remove_var(&name)? { 0 } else { 1 }),
+            (false, true) => env.set_universal(name, value)?,
+            (false, false) => return env.add_var(name, value).map(|_| 0)
+        };
+
+        match result {
+            Ok(_) => Ok(0),
+            Err(err) => {
+                eprintln!("set: could not update universal variables: {}", err);
+                Ok(1)
+            }
+        }
+    }
+
     fn eval_require(&self, _commands: &mut Iter<Command>, env: &mut Environment) -> Result<i32, Error> {
         if self.args.len() != 1 {
             eprintln!("require: expect arguments matching: require <file.sh>");
             Ok(2)
         }
         else {
-            let path = self.args.get(0).unwrap().eval(env)?;
+            let path = self.args.first().unwrap().eval(env)?;
             shell::run_script(path, env)
         }
     }
@@ -104,7 +406,7 @@ impl Command {
         match self.args.len() {
             0 => {
                 if let Some(path) = env.find_var(&String::from("HOME")) {
-                    shell::set_directory(&path.value())
+                    shell::set_directory(&path.value(), env)
                 }
                 else {
                     eprintln!("cd: $HOME environment variable not set.");
@@ -112,8 +414,8 @@ impl Command {
                 }
             },
             1 => {
-                let path = self.args.get(0).unwrap().eval(env)?;
-                shell::set_directory(&path)
+                let path = self.args.first().unwrap().eval(env)?;
+                shell::set_directory(&path, env)
             }
             _ => {
                 eprintln!("cd: too many arguments");
@@ -123,7 +425,7 @@ impl Command {
     }
 
     fn eval_if(&self, commands: &mut Iter<Command>, env: &mut Environment) -> Result<i32, Error> {
-        if self.args.len() == 0 {
+        if self.args.is_empty() {
             return Err(Error::WrongNumOfArgs("if", self.args.len(), 1usize));
         }
 
@@ -155,35 +457,987 @@ impl Command {
 
         let exit_code = Command::from_args(&self.args, env).unwrap().eval(commands, env)?;
         if exit_code == 0 {
-            for command in if_block.unwrap() {
-                command.eval(commands, env)?;
-            }
+            evaluate(if_block.unwrap(), env)?;
         }
         else {
-            for command in else_block {
-                command.eval(commands, env)?;
+            evaluate(else_block, env)?;
+        }
+
+        Ok(exit_code)
+    }
+
+    fn eval_while(&self, commands: &mut Iter<Command>, env: &mut Environment) -> Result<i32, Error> {
+        if self.args.is_empty() {
+            return Err(Error::WrongNumOfArgs("while", self.args.len(), 1usize));
+        }
+
+        let body = match parse_block_until(commands, |cmd| cmd.is_keyword("end")) {
+            Some(body) => body,
+            None => {
+                eprintln!("while: no end statement received");
+                return Ok(2);
+            }
+        };
+
+        // the status of the last body run, 0 if it never ran
+        let mut exit_code = 0;
+        while Command::from_args(&self.args, env)?.eval(&mut [].iter(), env)? == 0 {
+            exit_code = evaluate(body.clone(), env)?;
+
+            // an empty body never gets to check for Ctrl-C
+            if signal::take_interrupted() {
+                return Err(Error::Interrupted);
+            }
+        }
+
+        Ok(exit_code)
+    }
+
+    fn eval_def(&self, commands: &mut Iter<Command>, env: &mut Environment) -> Result<i32, Error> {
+        let body = parse_block_until(commands, |cmd| cmd.is_keyword("end"));
+        if body.is_none() {
+            eprintln!("def: no end statement received");
+            return Ok(2);
+        }
+
+        if self.args.len() != 1 {
+            eprintln!("def: expect arguments matching: def <name>");
+            return Ok(2);
+        }
+
+        let name = self.args.first().unwrap().eval(env)?;
+        env.add_ident(name, Function { body: body.unwrap() });
+        Ok(0)
+    }
+
+    fn eval_type(&self, _commands: &mut Iter<Command>, env: &mut Environment) -> Result<i32, Error> {
+        if self.args.is_empty() {
+            eprintln!("type: expect arguments matching: type <name...>");
+            return Ok(2);
+        }
+
+        let mut exit_code = 0;
+        for arg in &self.args {
+            let name = arg.eval(env)?;
+
+            if let Some(Alias { substitute }) = env.find_ident(&name) {
+                println!("{} is aliased to `{}`", name, substitute);
+            }
+            else if BUILTINS.contains_key(name.as_str()) {
+                println!("{} is a builtin", name);
+            }
+            else if let Some(Function { body }) = env.find_ident(&name) {
+                println!("{} is a function with definition", name);
+                print!("def {}\n{}end\n", name, format_block(body, 1));
+            }
+            else if let Some(path) = env.find_command(&name) {
+                println!("{} is {}", name, path.display());
+            }
+            else {
+                eprintln!("type: {}: not found", name);
+                exit_code = 1;
+            }
+        }
+
+        Ok(exit_code)
+    }
+
+    fn eval_which(&self, _commands: &mut Iter<Command>, env: &mut Environment) -> Result<i32, Error> {
+        if self.args.is_empty() {
+            eprintln!("which: expect arguments matching: which <name...>");
+            return Ok(2);
+        }
+
+        let mut exit_code = 0;
+        for arg in &self.args {
+            let name = arg.eval(env)?;
+            match env.find_command(&name) {
+                Some(path) => println!("{}", path.display()),
+                None => exit_code = 1
+            }
+        }
+
+        Ok(exit_code)
+    }
+
+    fn eval_rehash(&self, _commands: &mut Iter<Command>, env: &mut Environment) -> Result<i32, Error> {
+        env.rehash();
+        Ok(0)
+    }
+
+    fn eval_jobs(&self, _commands: &mut Iter<Command>, env: &mut Environment) -> Result<i32, Error> {
+        for job in env.jobs_mut().reap() {
+            println!("{}", job);
+        }
+
+        for job in env.jobs().jobs() {
+            println!("{}", job);
+        }
+
+        Ok(0)
+    }
+
+    // resolves the optional job spec argument of `fg` and `bg`
+    fn job_spec_arg(&self, builtin: &str, env: &mut Environment) -> Result<Option<usize>, Error> {
+        let spec = match self.args.len() {
+            0 => String::from("%%"),
+            1 => self.args.first().unwrap().eval(env)?,
+            _ => {
+                eprintln!("{}: too many arguments", builtin);
+                return Ok(None);
+            }
+        };
+
+        let id = env.jobs().parse_spec(&spec);
+        if id.is_none() {
+            eprintln!("{}: {}: no such job", builtin, spec);
+        }
+        Ok(id)
+    }
+
+    fn eval_fg(&self, _commands: &mut Iter<Command>, env: &mut Environment) -> Result<i32, Error> {
+        let id = match self.job_spec_arg("fg", env)? {
+            Some(id) => id,
+            None => return Ok(1)
+        };
+
+        println!("{}", env.jobs().get(id).unwrap().command().trim_end_matches(" &"));
+        Ok(env.jobs_mut().continue_foreground(id).unwrap_or_default())
+    }
+
+    fn eval_bg(&self, _commands: &mut Iter<Command>, env: &mut Environment) -> Result<i32, Error> {
+        let id = match self.job_spec_arg("bg", env)? {
+            Some(id) => id,
+            None => return Ok(1)
+        };
+
+        let job = env.jobs_mut().continue_background(id).unwrap();
+        println!("[{}] {}", job.id(), job.command());
+        Ok(0)
+    }
+
+    fn eval_wait(&self, _commands: &mut Iter<Command>, env: &mut Environment) -> Result<i32, Error> {
+        let mut any = false;
+        let mut ids = Vec::new();
+
+        for arg in &self.args {
+            let spec = arg.eval(env)?;
+            if spec == "-n" {
+                any = true;
+                continue;
+            }
+
+            let id = if spec.starts_with('%') {
+                env.jobs().parse_spec(&spec)
+            } else {
+                spec.parse().ok().and_then(|pid| env.jobs().find_pid(pid))
+            };
+
+            match id {
+                Some(id) => ids.push(id),
+                None => {
+                    eprintln!("wait: {}: no such job", spec);
+                    return Ok(127);
+                }
+            }
+        }
+
+        if ids.is_empty() {
+            ids = env.jobs().jobs().iter().map(|job| job.id()).collect();
+        }
+
+        // `wait -n` returns as soon as one of the jobs has finished
+        if any {
+            return Ok(match env.jobs_mut().wait_any(&ids) {
+                Some((_, exit_code)) => exit_code,
+                None => 127
+            });
+        }
+
+        // the exit code is the one of the last job waited for
+        let mut exit_code = 0;
+        for id in ids {
+            exit_code = env.jobs_mut().wait(id).unwrap_or(127);
+        }
+
+        Ok(exit_code)
+    }
+
+    fn eval_trap(&self, _commands: &mut Iter<Command>, env: &mut Environment) -> Result<i32, Error> {
+        let mut args = Vec::new();
+        for arg in &self.args {
+            args.push(arg.eval(env)?);
+        }
+
+        if args.is_empty() || (args.len() == 1 && args[0] == "-p") {
+            for (name, source) in env.traps() {
+                println!("trap -- '{}' {}", source, name);
+            }
+            return Ok(0);
+        }
+
+        if args.len() < 2 {
+            eprintln!("trap: expect arguments matching: trap <commands|-> <signal...>");
+            return Ok(2);
+        }
+
+        let source = args.remove(0);
+        let mut exit_code = 0;
+        for spec in args {
+            let (name, sig) = match spec.to_uppercase().as_str() {
+                "EXIT" | "0" => (String::from("EXIT"), None),
+                "ERR" => (String::from("ERR"), None),
+                _ => match signal::parse_signal(&spec) {
+                    Some(libc::SIGKILL) | Some(libc::SIGSTOP) => {
+                        eprintln!("trap: {}: cannot be trapped", spec);
+                        exit_code = 1;
+                        continue;
+                    }
+                    Some(sig) => (signal::signal_abbrev(sig).unwrap().to_string(), Some(sig)),
+                    None => {
+                        eprintln!("trap: {}: invalid signal specification", spec);
+                        exit_code = 1;
+                        continue;
+                    }
+                }
+            };
+
+            if source == "-" {
+                env.remove_trap(&name);
+                if let Some(sig) = sig {
+                    signal::untrap(sig);
+                }
+            }
+            else {
+                if let Some(sig) = sig {
+                    signal::trap(sig, source.is_empty());
+                }
+                env.set_trap(name, source.clone());
+            }
+        }
+
+        Ok(exit_code)
+    }
+
+    fn eval_kill(&self, _commands: &mut Iter<Command>, env: &mut Environment) -> Result<i32, Error> {
+        let mut args = Vec::new();
+        for arg in &self.args {
+            args.push(arg.eval(env)?);
+        }
+
+        if args.first().map(|arg| arg.as_str()) == Some("-l") {
+            if args.len() == 1 {
+                for (name, sig) in signal::SIGNALS.iter() {
+                    println!("{:>2}) SIG{}", sig, name);
+                }
+                return Ok(0);
+            }
+
+            let mut exit_code = 0;
+            for arg in &args[1..] {
+                // exit codes of signaled processes are 128 + the signal
+                let name = arg.parse::<i32>().ok()
+                    .and_then(|num| signal::signal_abbrev(if num > 128 { num - 128 } else { num }));
+                match name {
+                    Some(name) => println!("{}", name),
+                    None => {
+                        eprintln!("kill: {}: invalid signal specification", arg);
+                        exit_code = 1;
+                    }
+                }
+            }
+            return Ok(exit_code);
+        }
+
+        let mut sig = libc::SIGTERM;
+        if let Some(flag) = args.first().filter(|arg| arg.starts_with('-') && arg.len() > 1).cloned() {
+            args.remove(0);
+
+            let spec = match flag.as_str() {
+                "-s" | "-n" if !args.is_empty() => args.remove(0),
+                _ => flag[1..].to_string()
+            };
+
+            match signal::parse_signal(&spec) {
+                Some(num) => sig = num,
+                None => {
+                    eprintln!("kill: {}: invalid signal specification", spec);
+                    return Ok(2);
+                }
+            }
+        }
+
+        if args.is_empty() {
+            eprintln!("kill: expect arguments matching: kill [-<signal>|-s <signal>] <pid|%job...>");
+            return Ok(2);
+        }
+
+        let mut exit_code = 0;
+        for target in args {
+            let result = if target.starts_with('%') {
+                match env.jobs().parse_spec(&target) {
+                    Some(id) => env.jobs_mut().signal(id, sig),
+                    None => {
+                        eprintln!("kill: {}: no such job", target);
+                        exit_code = 1;
+                        continue;
+                    }
+                }
             }
+            else {
+                match target.parse::<libc::pid_t>() {
+                    Ok(pid) => job::signal_pid(pid, sig),
+                    Err(_) => {
+                        eprintln!("kill: {}: arguments must be process or job IDs", target);
+                        exit_code = 1;
+                        continue;
+                    }
+                }
+            };
+
+            if let Err(err) = result {
+                eprintln!("kill: {}: {}", target, err);
+                exit_code = 1;
+            }
+        }
+
+        Ok(exit_code)
+    }
+
+    // called before the redirections and `&` are applied, as they belong to the timed command
+    pub fn eval_time(&self, commands: &mut Iter<Command>, env: &mut Environment) -> Result<i32, Error> {
+        if self.is_in_background() {
+            return self.eval_time_in_background(env);
         }
 
+        let start = Instant::now();
+        let (shell_user, shell_system) = job::shell_times();
+        let (child_user, child_system) = job::child_times();
+
+        let exit_code = if self.args.is_empty() {
+            0
+        } else {
+            let command = Command {
+                callee: self.args[0].clone(),
+                args: self.args[1..].to_vec(),
+                redirects: self.redirects.clone(),
+                run_in_bg: false
+            };
+            command.eval(commands, env)?
+        };
+
+        let real = start.elapsed();
+        let (shell_user_end, shell_system_end) = job::shell_times();
+        let (child_user_end, child_system_end) = job::child_times();
+
+        let user = (shell_user_end - shell_user) + (child_user_end - child_user);
+        let system = (shell_system_end - shell_system) + (child_system_end - child_system);
+
+        eprintln!();
+        eprintln!("real\t{}", format_duration(real));
+        eprintln!("user\t{}", format_duration(user));
+        eprintln!("sys\t{}", format_duration(system));
+
         Ok(exit_code)
     }
+
+    // a background job of its own, in which a copy of the shell times the command
+    fn eval_time_in_background(&self, env: &mut Environment) -> Result<i32, Error> {
+        let mut words = vec![String::from("time")];
+        for arg in &self.args {
+            words.push(arg.eval(env)?);
+        }
+
+        let saved_fds = match redirect::apply(&self.redirects, env)? {
+            Ok(saved_fds) => saved_fds,
+            Err(err) => {
+                eprintln!("sheesh: {}", err);
+                return Ok(1);
+            }
+        };
+        let job_control = env.jobs().has_job_control();
+        let process = spawn_shell(words, job_control, env);
+        saved_fds.restore();
+
+        let pid = match process {
+            Ok(process) => process.id(),
+            Err(err) => {
+                eprintln!("time: {}", err);
+                return Ok(126);
+            }
+        };
+
+        if job_control {
+            job::set_process_group(pid);
+        }
+        env.jobs_mut().add(pid, self.to_string());
+        env.set_internal_var("!", pid.to_string());
+        Ok(0)
+    }
+
+    fn eval_ulimit(&self, _commands: &mut Iter<Command>, env: &mut Environment) -> Result<i32, Error> {
+        let (mut soft, mut hard, mut all) = (false, false, false);
+        let mut limit = Limit::find('f').unwrap();
+        let mut value = None;
+
+        for arg in &self.args {
+            let arg = arg.eval(env)?;
+            if !arg.starts_with('-') || arg.len() == 1 {
+                if value.is_some() {
+                    eprintln!("ulimit: too many arguments");
+                    return Ok(2);
+                }
+                value = Some(arg);
+                continue;
+            }
+
+            for c in arg.chars().skip(1) {
+                match c {
+                    'S' => soft = true,
+                    'H' => hard = true,
+                    'a' => all = true,
+                    _ => match Limit::find(c) {
+                        Some(found) => limit = found,
+                        None => {
+                            eprintln!("ulimit: unknown option `-{}`", c);
+                            return Ok(2);
+                        }
+                    }
+                }
+            }
+        }
+
+        // without -H, the soft limits are shown
+        let show = |limit: &Limit| -> Option<String> {
+            let (soft_value, hard_value) = limit.get().ok()?;
+            Some(limit.format(if hard && !soft { hard_value } else { soft_value }))
+        };
+
+        if all {
+            for limit in LIMITS.iter() {
+                let unit = if limit.unit.is_empty() { String::new() } else { format!("{}, ", limit.unit) };
+                let desc = format!("{} ({}-{})", limit.description, unit, limit.flag);
+                println!("{:<32}{}", desc, show(limit).unwrap_or_default());
+            }
+            return Ok(0);
+        }
+
+        let value = match value {
+            Some(value) => value,
+            None => {
+                println!("{}", show(limit).unwrap_or_default());
+                return Ok(0);
+            }
+        };
+
+        let new_value = match limit.parse(&value) {
+            Some(new_value) => new_value,
+            None => {
+                eprintln!("ulimit: {}: invalid limit", value);
+                return Ok(2);
+            }
+        };
+
+        // setting neither -S nor -H sets both limits
+        let (soft_value, hard_value) = limit.get().unwrap_or((new_value, new_value));
+        let result = match (soft, hard) {
+            (true, false) => limit.set(new_value, hard_value),
+            (false, true) => limit.set(soft_value.min(new_value), new_value),
+            _ => limit.set(new_value, new_value)
+        };
+
+        match result {
+            Ok(_) => Ok(0),
+            Err(err) => {
+                eprintln!("ulimit: {}: cannot modify limit: {}", limit.description, err);
+                Ok(1)
+            }
+        }
+    }
+
+    fn eval_umask(&self, _commands: &mut Iter<Command>, env: &mut Environment) -> Result<i32, Error> {
+        let mut symbolic = false;
+        let mut spec: Option<String> = None;
+
+        for arg in &self.args {
+            let arg = arg.eval(env)?;
+            if arg == "-S" {
+                symbolic = true;
+            }
+            else {
+                // `u=rwx` is split into three tokens at the `=`
+                spec = Some(spec.unwrap_or_default() + &arg);
+            }
+        }
+
+        let current = resource::get_umask();
+        match spec {
+            None if symbolic => println!("{}", resource::format_umask_symbolic(current)),
+            None => println!("{:04o}", current),
+            Some(spec) => match resource::parse_umask(&spec, current) {
+                Some(mask) => resource::set_umask(mask),
+                None => {
+                    eprintln!("umask: {}: invalid mode", spec);
+                    return Ok(2);
+                }
+            }
+        }
+
+        Ok(0)
+    }
+
+    fn eval_nice(&self, commands: &mut Iter<Command>, env: &mut Environment) -> Result<i32, Error> {
+        let mut args = self.args.as_slice();
+        let mut adjustment = 10;
+
+        // the adjustment is given as `-n N`, or in the old form `-N`
+        if let Some(flag) = args.first() {
+            let flag = flag.eval(env)?;
+            if let Some(option) = flag.strip_prefix('-') {
+                let value = if option == "n" && args.len() > 1 {
+                    let value = args[1].eval(env)?;
+                    args = &args[2..];
+                    value
+                }
+                else if option.starts_with(|c: char| c.is_ascii_digit() || c == '-') {
+                    args = &args[1..];
+                    option.to_string()
+                }
+                else {
+                    eprintln!("nice: expect arguments matching: nice [-n <adjustment>] [<command...>]");
+                    return Ok(2);
+                };
+
+                match value.parse::<i32>() {
+                    Ok(value) => adjustment = value,
+                    Err(_) => {
+                        eprintln!("nice: {}: invalid adjustment", value);
+                        return Ok(2);
+                    }
+                }
+            }
+        }
+
+        let niceness = match env.child_niceness() {
+            Some(niceness) => niceness,
+            None => match resource::get_niceness() {
+                Ok(niceness) => niceness,
+                Err(err) => {
+                    eprintln!("nice: cannot get niceness: {}", err);
+                    return Ok(1);
+                }
+            }
+        };
+        if args.is_empty() {
+            println!("{}", niceness);
+            return Ok(0);
+        }
+
+        // every process started by the command gets the new priority
+        let previous = env.child_niceness();
+        env.set_child_niceness(Some(niceness + adjustment));
+        let result = Command::from_args(args, env)?.eval(commands, env);
+        env.set_child_niceness(previous);
+        result
+    }
+
+    fn eval_exec(&self, _commands: &mut Iter<Command>, env: &mut Environment) -> Result<i32, Error> {
+        // without a command, the redirections apply to the shell itself
+        if self.args.is_empty() {
+            env.keep_redirections();
+            return Ok(0);
+        }
+
+        let mut argv = Vec::new();
+        for arg in &self.args {
+            argv.push(arg.eval(env)?);
+        }
+
+        let path = match env.find_command(&argv[0]) {
+            Some(path) => path,
+            None => {
+                eprintln!("exec: {}: not found", argv[0]);
+                return Ok(127);
+            }
+        };
+
+        io::stdout().flush().ok();
+
+        let c_path = CString::new(path.as_os_str().as_bytes());
+        let c_argv: Result<Vec<CString>, _> = argv.iter().map(|arg| CString::new(arg.as_str())).collect();
+        if let (Ok(c_path), Ok(c_argv)) = (c_path, c_argv) {
+            let mut ptrs: Vec<*const libc::c_char> = c_argv.iter().map(|arg| arg.as_ptr()).collect();
+            ptrs.push(std::ptr::null());
+
+            // only returns on failure
+            unsafe { libc::execv(c_path.as_ptr(), ptrs.as_ptr()) };
+        }
+
+        eprintln!("exec: {}: {}", argv[0], io::Error::last_os_error());
+        Ok(126)
+    }
+
+    fn eval_timeout(&self, _commands: &mut Iter<Command>, env: &mut Environment) -> Result<i32, Error> {
+        let mut args = self.args.as_slice();
+        let mut sig = libc::SIGTERM;
+        let mut kill_after = None;
+
+        while args.len() > 1 {
+            let flag = args[0].eval(env)?;
+            if flag != "-s" && flag != "-k" {
+                break;
+            }
+
+            let value = args[1].eval(env)?;
+            if flag == "-s" {
+                match signal::parse_signal(&value) {
+                    Some(parsed) => sig = parsed,
+                    None => {
+                        eprintln!("timeout: {}: invalid signal", value);
+                        return Ok(2);
+                    }
+                }
+            }
+            else {
+                match parse_timeout(&value) {
+                    Some(duration) => kill_after = Some(duration),
+                    None => {
+                        eprintln!("timeout: {}: invalid duration", value);
+                        return Ok(2);
+                    }
+                }
+            }
+            args = &args[2..];
+        }
+
+        if args.len() < 2 {
+            eprintln!("timeout: usage: timeout [-s SIGNAL] [-k DURATION] DURATION command...");
+            return Ok(2);
+        }
+
+        let value = args[0].eval(env)?;
+        let limit = match parse_timeout(&value) {
+            Some(limit) => limit,
+            None => {
+                eprintln!("timeout: {}: invalid duration", value);
+                return Ok(2);
+            }
+        };
+
+        let command = Command::from_args(&args[1..], env)?;
+        let mut words = Vec::new();
+        for arg in &args[1..] {
+            words.push(arg.eval(env)?);
+        }
+
+        // builtins, functions and aliases need a process of their own to be killable
+        let name = words[0].clone();
+        let process = if BUILTINS.contains_key(name.as_str()) || env.find_ident(&name).is_some() {
+            spawn_shell(words, true, env)
+        }
+        else {
+            match env.find_command(&name) {
+                Some(executable) => spawn_process(&name, &executable, &words[1..], true, env.child_niceness()),
+                None => return Err(Error::CommandNotFound(name))
+            }
+        };
+
+        let pid = match process {
+            Ok(process) => process.id(),
+            Err(err) => {
+                eprintln!("timeout: {}: {}", name, err);
+                return Ok(126);
+            }
+        };
+
+        job::set_process_group(pid);
+        Ok(env.jobs_mut().foreground_timeout(pid, command.to_string(), limit, sig, kill_after))
+    }
+
+    fn eval_history(&self, _commands: &mut Iter<Command>, env: &mut Environment) -> Result<i32, Error> {
+        let mut show_time = false;
+        let mut args = Vec::new();
+        for arg in &self.args {
+            let arg = arg.eval(env)?;
+            if arg == "-t" || arg == "--show-time" {
+                show_time = true;
+            }
+            else {
+                args.push(arg);
+            }
+        }
+
+        let subcommand = if args.is_empty() { String::from("list") } else { args.remove(0) };
+        let text = args.join(" ");
+
+        match subcommand.as_str() {
+            "list" | "search" => {
+                for (index, entry) in env.history().entries().iter().enumerate() {
+                    if entry.command.contains(&text) {
+                        print_history_entry(index + 1, entry, show_time);
+                    }
+                }
+                Ok(0)
+            }
+            "delete" if !args.is_empty() => {
+                let ids: Option<Vec<usize>> = args.iter().map(|arg| arg.parse().ok()).collect();
+                let result = match ids {
+                    Some(ids) => env.history_mut().remove(|index, _| ids.contains(&(index + 1))),
+                    None => env.history_mut().remove(|_, entry| entry.command == text)
+                };
+
+                match result {
+                    Ok(0) => {
+                        eprintln!("history: {}: no such entry", text);
+                        Ok(1)
+                    }
+                    Ok(_) => Ok(0),
+                    Err(err) => {
+                        eprintln!("history: {}", err);
+                        Ok(1)
+                    }
+                }
+            }
+            "clear" => match env.history_mut().remove(|_, _| true) {
+                Ok(_) => Ok(0),
+                Err(err) => {
+                    eprintln!("history: {}", err);
+                    Ok(1)
+                }
+            },
+            _ => {
+                eprintln!("history: usage: history [-t] [search TEXT | delete ID... | delete TEXT | clear]");
+                Ok(2)
+            }
+        }
+    }
+
+    fn eval_status(&self, _commands: &mut Iter<Command>, env: &mut Environment) -> Result<i32, Error> {
+        let subcommand = match self.args.first() {
+            Some(arg) => arg.eval(env)?,
+            None => {
+                println!("This is {}a login shell", if env.is_login() { "" } else { "not " });
+                println!("This is {}an interactive shell", if env.is_interactive() { "" } else { "not " });
+                return Ok(0);
+            }
+        };
+
+        let answer = match subcommand.as_str() {
+            "is-login" => env.is_login(),
+            "is-interactive" => env.is_interactive(),
+            _ => {
+                eprintln!("status: usage: status [is-login | is-interactive]");
+                return Ok(2);
+            }
+        };
+        Ok(if answer { 0 } else { 1 })
+    }
+
+    fn eval_complete(&self, _commands: &mut Iter<Command>, env: &mut Environment) -> Result<i32, Error> {
+        let mut args = Vec::new();
+        for arg in &self.args {
+            args.push(arg.eval(env)?);
+        }
+
+        let mut spec = CompletionSpec::default();
+        let mut erase = false;
+        let mut args = args.into_iter();
+
+        while let Some(flag) = args.next() {
+            // flags without a value
+            match flag.as_str() {
+                "-f" | "--no-files" => {
+                    spec.no_files = true;
+                    continue;
+                }
+                "-F" | "--force-files" => {
+                    spec.force_files = true;
+                    continue;
+                }
+                "-e" | "--erase" => {
+                    erase = true;
+                    continue;
+                }
+                _ => ()
+            }
+
+            let value = match args.next() {
+                Some(value) => value,
+                None => {
+                    eprintln!("complete: `{}` expects a value", flag);
+                    return Ok(2);
+                }
+            };
+
+            match flag.as_str() {
+                "-c" | "--command" => spec.command = value,
+                "-n" | "--condition" => spec.condition = Some(value),
+                "-a" | "--arguments" => spec.arguments = Some(value),
+                "-d" | "--description" => spec.description = value,
+                "-s" | "--short-option" => spec.short_options.push(value),
+                "-l" | "--long-option" => spec.long_options.push(value),
+                _ => {
+                    eprintln!("complete: unknown option `{}`", flag);
+                    return Ok(2);
+                }
+            }
+        }
+
+        let command = Some(spec.command.as_str()).filter(|command| !command.is_empty());
+        if erase {
+            return Ok(match command {
+                Some(command) if env.completions_mut().remove(command) => 0,
+                _ => 1
+            });
+        }
+
+        // only a command, or nothing at all, lists the specs
+        if spec == (CompletionSpec { command: spec.command.clone(), ..Default::default() }) {
+            for spec in env.completions().specs(command) {
+                println!("{}", spec);
+            }
+            return Ok(0);
+        }
+
+        if command.is_none() {
+            eprintln!("complete: expect a command with `-c <command>`");
+            return Ok(2);
+        }
+
+        env.completions_mut().add(spec);
+        Ok(0)
+    }
+}
+
+fn print_history_entry(id: usize, entry: &HistoryEntry, show_time: bool) {
+    let time = if show_time { format!("{}  ", history::format_time(entry.timestamp)) } else { String::new() };
+    let indent = " ".repeat(7 + time.len());
+    println!("{:>5}  {}{}", id, time, entry.command.replace('\n', &format!("\n{}", indent)));
+}
+
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs_f64();
+    format!("{}m{:.3}s", (secs / 60.0).floor(), secs % 60.0)
+}
+
+// durations like `10`, `2.5s`, `1m`, `2h` or `1d`
+fn parse_timeout(value: &str) -> Option<Duration> {
+    let (number, factor) = match value.chars().last()? {
+        's' => (&value[..value.len() - 1], 1.0),
+        'm' => (&value[..value.len() - 1], 60.0),
+        'h' => (&value[..value.len() - 1], 3600.0),
+        'd' => (&value[..value.len() - 1], 86400.0),
+        _ => (value, 1.0)
+    };
+
+    // the deadline has to be representable, too
+    let duration = Duration::try_from_secs_f64(number.parse::<f64>().ok()? * factor).ok()?;
+    Instant::now().checked_add(duration)?;
+    Some(duration)
+}
+
+// the aliases and functions as source code, for a new copy of the shell to run
+// starts a new copy of the shell that runs the words, passed as `$1`, `$2`...
+fn spawn_shell(words: Vec<String>, setpgid: bool, env: &mut Environment) -> io::Result<process::Child> {
+    let call: Vec<String> = (1..=words.len()).map(|i| format!("${}", i)).collect();
+    let mut shell_args = vec![
+        String::from("-c"),
+        format!("{}{}\n", definitions(env), call.join(" "))
+    ];
+    shell_args.extend(words);
+
+    process_command("sheesh", &current_exe()?, &shell_args, setpgid, env.child_niceness())
+        .env(shell::NO_CONFIG_VAR, "1")
+        .spawn()
+}
+
+// the state a new copy of the shell needs to run a builtin or function like this one
+// would; `exec` redirections are inherited as open descriptors
+fn definitions(env: &mut Environment) -> String {
+    let mut source = String::new();
+    for (name, var) in env.variables() {
+        // the new shell sets its own `$`, `status` and arguments, and inherits exported values
+        let is_name = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !is_name || name == "argv" || name == "status" || name == "PWD" {
+            continue;
+        }
+
+        let value = var.value();
+        if var.is_read_only() {
+            source += &format!("readonly {} = {}\n", name, complete::quote(&value));
+        }
+        else if env::var(name).ok() != Some(value.clone()) {
+            source += &format!("set {} {}\n", name, complete::quote(&value));
+        }
+    }
+
+    // the exit trap runs when this shell exits, not the copy
+    for (name, trap) in env.traps() {
+        if name != "EXIT" {
+            source += &format!("trap {} {}\n", complete::quote(trap), name);
+        }
+    }
+
+    for (name, substitute) in env.aliases() {
+        source += &format!("alias {} = {}\n", name, substitute);
+    }
+
+    for name in env.functions() {
+        if let Some(Function { body }) = env.find_ident(&name) {
+            source += &format!("def {}\n{}end\n", name, format_block(body, 1));
+        }
+    }
+    source
 }
 
 fn parse_block_until<F>(commands: &mut Iter<Command>, mut is_end: F) -> Option<Vec<Command>> 
     where F: FnMut(&Command) -> bool
 {
     let mut block = Vec::new();
+    let mut depth = 0usize; // nested blocks have to be skipped
 
     loop {
         if let Some(next) = commands.next() {
-            if is_end(next) {
+            if depth == 0 && is_end(next) {
+                // `else` and `end` take no arguments
+                if !next.args.is_empty() {
+                    eprintln!("{}: too many arguments", next.callee);
+                }
                 return Some(block)
             }
 
+            if next.starts_block() {
+                depth += 1;
+            }
+            else if depth > 0 && next.is_keyword("end") {
+                depth -= 1;
+            }
+
             block.push(next.clone())
         }
         else {
             return None;
         }
     }
+}
+
+fn format_block(block: &[Command], indent: usize) -> String {
+    let mut source = String::new();
+    let mut depth = indent;
+
+    for command in block {
+        let closes = command.is_keyword("end") || command.is_keyword("else");
+        if closes && depth > indent {
+            depth -= 1;
+        }
+
+        source.push_str(&format!("{}{}\n", "    ".repeat(depth), command));
+
+        if command.starts_block() || command.is_keyword("else") {
+            depth += 1;
+        }
+    }
+
+    source
 }
\ No newline at end of file
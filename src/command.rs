@@ -1,5 +1,8 @@
+use std::fmt;
+
 use crate::{
     shell::Error,
+    redirect::Redirect,
     token::{
         Token, 
         TokenKind::{
@@ -8,6 +11,10 @@ use crate::{
     }
 };
 
+// keywords opening a block, which has to be closed with `end`
+pub const BLOCK_KEYWORDS: [&str; 3] = ["if", "while", "def"];
+
+#[allow(dead_code)]
 #[derive(Debug, Clone)]
 pub enum Value {
     PlainText(TokenKind, String),
@@ -16,7 +23,7 @@ pub enum Value {
 }
 
 impl Value {
-    fn parse(tok: &Token) -> Self {
+    pub fn parse(tok: &Token) -> Self {
         match tok.kind() {
             STRING => Value::String(tok.value().clone()),
             VARIABLE => Value::Variable(tok.value()[1..].to_string()),
@@ -25,18 +32,30 @@ impl Value {
     }
 }
 
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::PlainText(_, text) => write!(f, "{}", text),
+            Self::String(str) => write!(f, "{}", str),
+            Self::Variable(name) => write!(f, "${}", name)
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Command {
     pub callee: Value,
     pub args: Vec<Value>,
+    pub redirects: Vec<Redirect>,
     pub run_in_bg: bool
 }
 
 impl Command {
-    fn parse(tokens: &mut std::slice::Iter<Token>, callee: Value) -> Self {
+    fn parse(tokens: &mut std::slice::Iter<Token>, callee: Value) -> Result<Self, Error> {
         let mut cmd = Command {
             callee,
             args: Vec::new(),
+            redirects: Vec::new(),
             run_in_bg: false
         };
 
@@ -49,11 +68,12 @@ impl Command {
                     cmd.run_in_bg = true;
                     break;
                 }
-                _ => cmd.args.push(Value::parse(&tok))
+                INSTREAM | OUTSTREAM | APPEND => cmd.redirects.push(Redirect::parse(tok, tokens)?),
+                _ => cmd.args.push(Value::parse(tok))
             }
         }
 
-        cmd
+        Ok(cmd)
     }
 
     pub fn is_in_background(&self) -> bool {
@@ -62,24 +82,39 @@ impl Command {
 
     pub fn combine(&mut self, other: &Command) -> &Self {
         self.args.append(&mut other.args.clone());
+        self.redirects.append(&mut other.redirects.clone());
+        self.run_in_bg |= other.run_in_bg;
         self
     }
 
-    pub fn is_keyword<'a>(&self, keyword: &'a str) -> bool {
-        match self.callee.clone() {
-            Value::PlainText(_, text) => {
-                if text.eq(keyword) {
-                    if self.args.len() != 0 {
-                        eprintln!("{}: too many arguments", keyword)
-                    }
-                    true
-                } else {
-                    false
-                }
-            }
+    pub fn starts_block(&self) -> bool {
+        match &self.callee {
+            Value::PlainText(_, text) => BLOCK_KEYWORDS.contains(&text.as_str()),
             _ => false
         }
     }
+
+    pub fn is_keyword(&self, keyword: &str) -> bool {
+        matches!(&self.callee, Value::PlainText(_, text) if text == keyword)
+    }
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.callee)?;
+        for arg in &self.args {
+            write!(f, " {}", arg)?;
+        }
+
+        for redirect in &self.redirects {
+            write!(f, " {}", redirect)?;
+        }
+
+        if self.run_in_bg {
+            write!(f, " &")?;
+        }
+        Ok(())
+    }
 }
 
 pub fn parse(tokens: Vec<Token>) -> Result<Vec<Command>, Error> {
@@ -95,10 +130,27 @@ pub fn parse(tokens: Vec<Token>) -> Result<Vec<Command>, Error> {
                 continue;
             }
             _ => {
-                commands.push(Command::parse(&mut iter, Value::parse(&tok)));
+                commands.push(Command::parse(&mut iter, Value::parse(tok))?);
             }
         }
     }
 
+    if has_open_block(&commands) {
+        return Err(Error::Incomplete("Expected `end`"));
+    }
+
     Ok(commands)
+}
+
+fn has_open_block(commands: &[Command]) -> bool {
+    let mut depth = 0usize;
+    for cmd in commands {
+        if cmd.starts_block() {
+            depth += 1;
+        }
+        else if matches!(&cmd.callee, Value::PlainText(_, text) if text == "end") {
+            depth = depth.saturating_sub(1);
+        }
+    }
+    depth > 0
 }
\ No newline at end of file
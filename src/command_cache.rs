@@ -0,0 +1,58 @@
+use std::{
+    collections::HashMap,
+    env,
+    fs,
+    os::unix::fs::PermissionsExt,
+    path::{
+        Path,
+        PathBuf
+    }
+};
+
+// remembers where external commands were found on `$PATH`, so that it does not have to be
+// searched on every call
+pub struct CommandCache {
+    path: String,
+    commands: HashMap<String, PathBuf>
+}
+
+impl CommandCache {
+    pub fn new() -> Self {
+        Self {
+            path: String::new(),
+            commands: HashMap::new()
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.commands.clear();
+    }
+
+    pub fn lookup(&mut self, name: &String, path: &String) -> Option<PathBuf> {
+        // names containing a slash are never looked up in `$PATH`
+        if name.contains('/') {
+            let file = PathBuf::from(name);
+            return if is_executable(&file) { Some(file) } else { None };
+        }
+
+        if *path != self.path {
+            self.path = path.clone();
+            self.clear();
+        }
+
+        if let Some(file) = self.commands.get(name).filter(|file| is_executable(file)) {
+            return Some(file.clone());
+        }
+
+        let file = env::split_paths(path)
+            .map(|dir| dir.join(name))
+            .find(|file| is_executable(file))?;
+
+        self.commands.insert(name.clone(), file.clone());
+        Some(file)
+    }
+}
+
+pub fn is_executable(file: &Path) -> bool {
+    fs::metadata(file).is_ok_and(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+}
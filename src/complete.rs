@@ -0,0 +1,438 @@
+use std::{
+    collections::HashSet,
+    env,
+    fmt,
+    fs,
+    path::Path
+};
+
+use crate::{
+    builtin::BUILTINS,
+    command::BLOCK_KEYWORDS,
+    command_cache::is_executable,
+    environment::Environment,
+    generate,
+    shell
+};
+
+// characters ending a word, like the symbols of the tokenizer
+pub const WORD_SEPARATORS: &str = ";|&<>=";
+
+// characters after which a new command starts
+const COMMAND_SEPARATORS: &str = ";|&\n";
+
+// builtins and keywords taking a command as their first argument
+pub const PRECOMMANDS: [&str; 4] = ["if", "time", "exec", "nice"];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Candidate {
+    pub replacement: String, // replaces the word being completed
+    pub display: String,
+    pub description: String,
+    pub add_space: bool
+}
+
+impl Candidate {
+    fn new(replacement: String, description: &str) -> Self {
+        Self {
+            display: replacement.clone(),
+            replacement,
+            description: description.to_string(),
+            add_space: true
+        }
+    }
+}
+
+// a `complete` rule for the arguments of a command
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CompletionSpec {
+    pub command: String,
+    pub condition: Option<String>, // shell code, the spec only applies if it succeeds
+    pub arguments: Option<String>, // words, or `(code)` printing one candidate per line
+    pub description: String,
+    pub short_options: Vec<String>,
+    pub long_options: Vec<String>,
+    pub no_files: bool,
+    pub force_files: bool
+}
+
+impl fmt::Display for CompletionSpec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "complete -c {}", quote(&self.command))?;
+        if let Some(condition) = &self.condition {
+            write!(f, " -n {}", quote(condition))?;
+        }
+        for option in &self.short_options {
+            write!(f, " -s {}", quote(option))?;
+        }
+        for option in &self.long_options {
+            write!(f, " -l {}", quote(option))?;
+        }
+        if let Some(arguments) = &self.arguments {
+            write!(f, " -a {}", quote(arguments))?;
+        }
+        if !self.description.is_empty() {
+            write!(f, " -d {}", quote(&self.description))?;
+        }
+        if self.no_files {
+            write!(f, " -f")?;
+        }
+        if self.force_files {
+            write!(f, " -F")?;
+        }
+        Ok(())
+    }
+}
+
+// the specs registered with `complete`
+pub struct Completions {
+    specs: Vec<CompletionSpec>,
+    autoloaded: HashSet<String> // commands whose completion file was already looked for
+}
+
+impl Completions {
+    pub fn new() -> Self {
+        Self {
+            specs: Vec::new(),
+            autoloaded: HashSet::new()
+        }
+    }
+
+    pub fn add(&mut self, spec: CompletionSpec) {
+        if !self.specs.contains(&spec) {
+            self.specs.push(spec);
+        }
+    }
+
+    pub fn remove(&mut self, command: &str) -> bool {
+        let before = self.specs.len();
+        self.specs.retain(|spec| spec.command != command);
+        self.specs.len() != before
+    }
+
+    pub fn specs(&self, command: Option<&str>) -> Vec<&CompletionSpec> {
+        self.specs.iter().filter(|spec| command.is_none_or(|command| spec.command == command)).collect()
+    }
+}
+
+// completes the word at the end of `line`; returns where the word starts, in chars
+pub fn complete(line: &str, env: &mut Environment) -> (usize, Vec<Candidate>) {
+    let chars: Vec<char> = line.chars().collect();
+    let start = chars.iter()
+        .rposition(|c| c.is_whitespace() || WORD_SEPARATORS.contains(*c))
+        .map_or(0, |pos| pos + 1);
+
+    let word: String = chars[start..].iter().collect();
+    let before: String = chars[..start].iter().collect();
+
+    let candidates = if let Some(name) = word.strip_prefix('$') {
+        complete_variables(name, env)
+    }
+    else if is_command_position(&before) {
+        if word.contains('/') {
+            complete_files(&word, true)
+        } else {
+            complete_commands(&word, env)
+        }
+    }
+    else {
+        complete_arguments(&before, &word, env)
+    };
+
+    (start, candidates)
+}
+
+// uses the specs for the command, and files unless they say otherwise
+fn complete_arguments(before: &str, word: &str, env: &mut Environment) -> Vec<Candidate> {
+    let segment = before.rsplit(|c| COMMAND_SEPARATORS.contains(c)).next().unwrap_or_default();
+    let mut words: Vec<String> = segment.split_whitespace().map(String::from).collect();
+    if words.first().is_some_and(|first| PRECOMMANDS.contains(&first.as_str())) {
+        words.remove(0);
+    }
+
+    let command = match words.first() {
+        Some(command) => command.clone(),
+        None => return complete_files(word, false)
+    };
+    autoload(&command, env);
+
+    let specs: Vec<CompletionSpec> = env.completions().specs(Some(&command)).into_iter().cloned().collect();
+    if specs.is_empty() {
+        return complete_files(word, false);
+    }
+
+    let mut candidates = Vec::new();
+    let (mut no_files, mut force_files) = (false, false);
+
+    for spec in specs {
+        if let Some(condition) = &spec.condition {
+            if !run_in_command_line(condition, &words[1..], word, env).0 {
+                continue;
+            }
+        }
+
+        no_files |= spec.no_files;
+        force_files |= spec.force_files;
+
+        if word.starts_with('-') {
+            let options = spec.short_options.iter().map(|option| format!("-{}", option))
+                .chain(spec.long_options.iter().map(|option| format!("--{}", option)));
+            for option in options.filter(|option| option.starts_with(word)) {
+                candidates.push(Candidate::new(option, &spec.description));
+            }
+            continue;
+        }
+
+        let arguments = match &spec.arguments {
+            Some(arguments) => arguments,
+            None => continue
+        };
+
+        // `(code)` prints the candidates, each optionally followed by a tab and a description
+        let dynamic = arguments.strip_prefix('(').and_then(|code| code.strip_suffix(')'));
+        let values: Vec<(String, String)> = match dynamic {
+            Some(code) => run_in_command_line(code, &words[1..], word, env).1.lines()
+                .map(|line| match line.split_once('\t') {
+                    Some((value, description)) => (value.to_string(), description.to_string()),
+                    None => (line.to_string(), spec.description.clone())
+                })
+                .collect(),
+            None => arguments.split_whitespace().map(|value| (value.to_string(), spec.description.clone())).collect()
+        };
+
+        for (value, description) in values {
+            if value.starts_with(word) && !candidates.iter().any(|candidate: &Candidate| candidate.replacement == value) {
+                candidates.push(Candidate::new(value, &description));
+            }
+        }
+    }
+
+    if force_files || !no_files {
+        candidates.append(&mut complete_files(word, false));
+    }
+    candidates
+}
+
+// loads `$XDG_CONFIG_HOME/sheesh/completions/<command>.sh`, or the generated file for the
+// command, the first time it's needed
+fn autoload(command: &str, env: &mut Environment) {
+    if command.contains('/') || !env.completions_mut().autoloaded.insert(command.to_string()) {
+        return;
+    }
+
+    // completions written by hand take precedence over generated ones
+    let file_name = format!("{}.sh", command);
+    let path = shell::config_dir().map(|dir| dir.join("completions").join(&file_name))
+        .into_iter()
+        .chain(generate::generated_dir().map(|dir| dir.join(&file_name)))
+        .find(|path| path.is_file());
+
+    if let Some(path) = path {
+        let path = path.to_string_lossy().to_string();
+        if let Err(err) = shell::run_script(path.clone(), env) {
+            eprintln!("sheesh: {}: {}", path, err);
+        }
+    }
+}
+
+// runs a condition or candidate source with the arguments typed so far as `$1`.. and
+// `$argv`, and the word being completed as `$current`; returns whether it succeeded
+// and what it printed
+fn run_in_command_line(code: &str, args: &[String], current: &str, env: &mut Environment) -> (bool, String) {
+    env.push_scope();
+    let result = (|| {
+        env.add_var(String::from("argv"), args.join(" "))?;
+        for (i, arg) in args.iter().enumerate() {
+            env.add_var((i + 1).to_string(), arg.clone())?;
+        }
+        env.add_var(String::from("current"), current.to_string())
+    })();
+
+    let output = match result {
+        Ok(_) => shell::capture_output(code, env).unwrap_or((false, String::new())),
+        Err(_) => (false, String::new())
+    };
+
+    env.pop_scope();
+    output
+}
+
+pub fn is_command_position(before: &str) -> bool {
+    let before = before.trim_end();
+    if before.is_empty() || before.ends_with(|c| COMMAND_SEPARATORS.contains(c)) {
+        return true;
+    }
+
+    let previous = before.rsplit(|c: char| c.is_whitespace() || COMMAND_SEPARATORS.contains(c)).next();
+    previous.is_some_and(|previous| PRECOMMANDS.contains(&previous))
+}
+
+fn complete_variables(prefix: &str, env: &Environment) -> Vec<Candidate> {
+    env.variables().into_iter()
+        .filter(|(name, _)| name.starts_with(prefix))
+        .map(|(name, _)| Candidate::new(format!("${}", name), ""))
+        .collect()
+}
+
+fn complete_commands(prefix: &str, env: &Environment) -> Vec<Candidate> {
+    let mut candidates = Vec::new();
+    let mut add = |name: &str, description: &str| {
+        if name.starts_with(prefix) && !candidates.iter().any(|candidate: &Candidate| candidate.replacement == name) {
+            candidates.push(Candidate::new(name.to_string(), description));
+        }
+    };
+
+    for keyword in BLOCK_KEYWORDS.iter().chain(["else", "end"].iter()) {
+        add(keyword, "keyword");
+    }
+
+    for name in BUILTINS.keys() {
+        add(name, "builtin");
+    }
+
+    for (name, _) in env.aliases() {
+        add(&name, "alias");
+    }
+
+    for name in env.functions() {
+        add(&name, "function");
+    }
+
+    let path = env.find_var(&String::from("PATH")).map(|var| var.value()).unwrap_or_default();
+    for dir in env::split_paths(&path) {
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(_) => continue
+        };
+
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if name.starts_with(prefix) && is_executable(&entry.path()) {
+                add(&name, "");
+            }
+        }
+    }
+
+    candidates.sort_by(|a, b| a.replacement.cmp(&b.replacement));
+    candidates
+}
+
+fn complete_files(word: &str, executables_only: bool) -> Vec<Candidate> {
+    let (dir, prefix) = match word.rfind('/') {
+        Some(pos) => (&word[..pos + 1], &word[pos + 1..]),
+        None => ("", word)
+    };
+
+    let entries = match fs::read_dir(if dir.is_empty() { Path::new(".") } else { Path::new(dir) }) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new()
+    };
+
+    let mut candidates = Vec::new();
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+
+        // hidden files only if asked for
+        if !name.starts_with(prefix) || (name.starts_with('.') && !prefix.starts_with('.')) {
+            continue;
+        }
+
+        let path = entry.path();
+        let is_dir = path.is_dir();
+        if executables_only && !is_dir && !is_executable(&path) {
+            continue;
+        }
+
+        let suffix = if is_dir { "/" } else { "" };
+        candidates.push(Candidate {
+            replacement: format!("{}{}{}", dir, name, suffix),
+            display: format!("{}{}", name, suffix),
+            description: String::new(),
+            add_space: !is_dir
+        });
+    }
+
+    candidates.sort_by(|a, b| a.replacement.cmp(&b.replacement));
+    candidates
+}
+
+// puts words the tokenizer would split into quotes; text with a `'` goes into double
+// quotes, where `"` and `\` are escaped
+pub fn quote(text: &str) -> String {
+    if text.is_empty() {
+        String::from("''")
+    }
+    else if text.contains('\'') {
+        format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+    }
+    else if text.contains(|c: char| c.is_whitespace() || WORD_SEPARATORS.contains(c) || c == '"' || c == '#') {
+        format!("'{}'", text)
+    }
+    else {
+        text.to_string()
+    }
+}
+
+// the longest text all candidates start with
+pub fn common_prefix(candidates: &[Candidate]) -> String {
+    let mut prefix: Vec<char> = match candidates.first() {
+        Some(first) => first.replacement.chars().collect(),
+        None => return String::new()
+    };
+
+    for candidate in &candidates[1..] {
+        let len = prefix.iter().zip(candidate.replacement.chars()).take_while(|(a, b)| **a == *b).count();
+        prefix.truncate(len);
+    }
+
+    prefix.into_iter().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::token::{
+        self,
+        TokenKind
+    };
+
+    // the word the tokenizer reads back from the quoted text
+    fn read_back(quoted: &str) -> String {
+        let tokens: Vec<_> = token::tokenize(quoted.to_string()).unwrap().into_iter()
+            .filter(|tok| !matches!(tok.kind(), TokenKind::EOF | TokenKind::NEWLINE))
+            .collect();
+        assert_eq!(tokens.len(), 1, "`{}` is not a single word", quoted);
+
+        match tokens[0].kind() {
+            TokenKind::STRING => token::unquote(tokens[0].value()),
+            _ => tokens[0].value().clone()
+        }
+    }
+
+    #[test]
+    fn quoted_text_reads_back_unchanged() {
+        let texts = [
+            "plain",
+            "two words",
+            "a;b|c",
+            "# not a comment",
+            "don't",
+            "say \"hi\"",
+            "don't say \"hi\"",
+            "C:\\dir\\ and 'quotes' \"both\"",
+            "ends with a backslash \\",
+            "it's \\\"escaped\\\""
+        ];
+
+        for text in texts {
+            assert_eq!(read_back(&quote(text)), text, "quoted as {}", quote(text));
+        }
+    }
+
+    #[test]
+    fn quote_only_when_needed() {
+        assert_eq!(quote("--all"), "--all");
+        assert_eq!(quote("two words"), "'two words'");
+        assert_eq!(quote("don't \"quote\""), "\"don't \\\"quote\\\"\"");
+    }
+}
@@ -0,0 +1,1062 @@
+use std::{
+    collections::HashMap,
+    env,
+    io::{
+        self,
+        BufRead,
+        Write
+    },
+    mem
+};
+
+use libc::termios;
+use unicode_width::{
+    UnicodeWidthChar,
+    UnicodeWidthStr
+};
+
+use crate::{
+    complete::{
+        self,
+        Candidate
+    },
+    environment::Environment,
+    highlight,
+    history::History,
+    prompt::Prompt,
+    shell,
+    signal,
+    token::{
+        self,
+        TokenKind
+    }
+};
+
+#[derive(Debug, Clone, PartialEq)]
+enum Key {
+    Char(char),
+    Ctrl(char),
+    Alt(char),
+    Paste(String),
+    Enter,
+    AltEnter,
+    Tab,
+    BackTab,
+    Backspace,
+    AltBackspace,
+    Delete,
+    Left,
+    Right,
+    Up,
+    Down,
+    Home,
+    End,
+    WordLeft,
+    WordRight,
+    Escape,
+    Unknown
+}
+
+// what a key press asks the editor to do with the line
+enum Action {
+    Continue,
+    Submit,
+    Cancel,
+    Eof
+}
+
+// what the user entered at the prompt
+#[derive(Debug, PartialEq)]
+pub enum Input {
+    Line(String), // complete input, unless the input ended in the middle of it
+    Interrupted,  // Ctrl-C
+    Eof
+}
+
+// state of an incremental history search with Ctrl-R
+struct Search {
+    query: String,
+    index: Option<usize>, // of the matching history entry
+    original: Vec<char>
+}
+
+// completion candidates shown under the input
+struct Menu {
+    candidates: Vec<Candidate>,
+    selected: Option<usize>,
+    start: usize, // of the completed word in the buffer
+    original: Vec<char>
+}
+
+// puts the terminal into raw mode for as long as it lives
+struct RawMode {
+    original: termios,
+    raw: termios
+}
+
+impl RawMode {
+    fn enable() -> io::Result<Self> {
+        let mut original: termios = unsafe { mem::zeroed() };
+        if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut original) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut raw = original;
+        raw.c_iflag &= !(libc::ICRNL | libc::IXON | libc::BRKINT | libc::INPCK | libc::ISTRIP);
+        raw.c_lflag &= !(libc::ECHO | libc::ICANON | libc::ISIG | libc::IEXTEN);
+        raw.c_cc[libc::VMIN] = 1;
+        raw.c_cc[libc::VTIME] = 0;
+
+        if unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSADRAIN, &raw) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        // pasted text arrives in one piece instead of as key presses
+        write_str("\x1b[?2004h")?;
+        Ok(Self { original, raw })
+    }
+
+    // commands run for completions leave the terminal in its normal mode
+    fn reapply(&self) -> io::Result<()> {
+        if unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSADRAIN, &self.raw) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        write_str("\x1b[?2004l").ok();
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSADRAIN, &self.original) };
+    }
+}
+
+// the line editor of the REPL, with emacs-like key bindings
+pub struct Editor {
+    buffer: Vec<char>,
+    cursor: usize,
+    prompt: String,
+    right_prompt: String,
+    continuation_prompt: String,
+    transient_prompt: Option<String>,
+    kill_buffer: Vec<char>,
+    history_index: Option<usize>, // the history entry shown, while going through it
+    history_prefix: Vec<char>,    // what was typed before going through the history
+    search: Option<Search>,
+    menu: Option<Menu>,
+    suggestion: Vec<char>, // the rest of a history entry, shown dimmed after the input
+    below: String, // shown under the input
+    columns: usize,
+    rows: usize,
+    cursor_row: usize // row of the cursor below the first prompt row, as last drawn
+}
+
+impl Editor {
+    pub fn new() -> Self {
+        Self {
+            buffer: Vec::new(),
+            cursor: 0,
+            prompt: String::new(),
+            right_prompt: String::new(),
+            continuation_prompt: String::new(),
+            transient_prompt: None,
+            kill_buffer: Vec::new(),
+            history_index: None,
+            history_prefix: Vec::new(),
+            search: None,
+            menu: None,
+            suggestion: Vec::new(),
+            below: String::new(),
+            columns: 80,
+            rows: 24,
+            cursor_row: 0
+        }
+    }
+
+    // reads input including its final newline, with more lines while a block, string or
+    // line continuation is open
+    pub fn read_line(&mut self, prompt: &Prompt, env: &mut Environment) -> io::Result<Input> {
+        if unsafe { libc::isatty(libc::STDIN_FILENO) } == 0 {
+            return read_plain_lines(prompt);
+        }
+
+        let raw_mode = RawMode::enable()?;
+        self.buffer.clear();
+        self.cursor = 0;
+        self.prompt = prompt.left.clone();
+        self.right_prompt = prompt.right.clone();
+        self.continuation_prompt = prompt.continuation.clone();
+        self.transient_prompt = prompt.transient.clone();
+        self.history_index = None;
+        self.search = None;
+        self.menu = None;
+        self.suggestion.clear();
+        self.below.clear();
+        self.cursor_row = 0;
+        (self.columns, self.rows) = terminal_size();
+        self.redraw(env)?;
+
+        loop {
+            let mut key = match read_key() {
+                Ok(Some(key)) => key,
+                Ok(None) => {
+                    // interrupted by a signal
+                    if signal::take_resized() {
+                        self.resize(env)?;
+                    }
+                    continue;
+                }
+                // the terminal hung up, even in the middle of a sequence: like Ctrl-D
+                Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => {
+                    self.finish(env, "\n")?;
+                    return Ok(Input::Eof);
+                }
+                Err(err) => return Err(err)
+            };
+
+            if self.search.is_some() {
+                key = match self.handle_search_key(key, env.history()) {
+                    Some(key) => key,
+                    None => {
+                        self.redraw(env)?;
+                        continue;
+                    }
+                };
+            }
+
+            if self.menu.is_some() {
+                key = match self.handle_menu_key(key) {
+                    Some(key) => key,
+                    None => {
+                        self.redraw(env)?;
+                        continue;
+                    }
+                };
+            }
+
+            if !matches!(key, Key::Up | Key::Down) {
+                self.history_index = None;
+            }
+
+            let completing = key == Key::Tab;
+            let action = self.handle_key(key, env)?;
+            if completing {
+                raw_mode.reapply()?;
+            }
+
+            match action {
+                Action::Continue => {
+                    self.update_suggestion(env);
+                    self.redraw(env)?;
+                }
+                Action::Submit => {
+                    let mut line: String = self.buffer.iter().collect();
+                    line.push('\n');
+
+                    // Enter within an open block or string starts a new line of it
+                    if shell::is_incomplete(&line) {
+                        self.insert(&['\n']);
+                        self.update_suggestion(env);
+                        self.redraw(env)?;
+                        continue;
+                    }
+
+                    self.use_transient_prompt();
+                    self.finish(env, "\n")?;
+                    return Ok(Input::Line(line));
+                }
+                Action::Cancel => {
+                    self.use_transient_prompt();
+                    self.finish(env, "^C\n")?;
+                    signal::record_interrupt();
+                    return Ok(Input::Interrupted);
+                }
+                Action::Eof => {
+                    self.finish(env, "\n")?;
+                    return Ok(Input::Eof);
+                }
+            }
+        }
+    }
+
+    fn handle_key(&mut self, key: Key, env: &mut Environment) -> io::Result<Action> {
+        // a space or Enter after an abbreviation expands it
+        let placed_cursor = matches!(key, Key::Char(' ') | Key::Enter) && self.expand_abbreviation(env);
+
+        let history = env.history();
+        match key {
+            Key::Enter => return Ok(Action::Submit),
+            Key::Ctrl('c') => return Ok(Action::Cancel),
+            Key::Ctrl('d') if self.buffer.is_empty() => return Ok(Action::Eof),
+
+            // no space after an expansion that placed the cursor itself
+            Key::Char(' ') if placed_cursor => (),
+            Key::Char(c) => self.insert(&[c]),
+            Key::Paste(text) => {
+                let text: Vec<char> = text.replace("\r\n", "\n").replace('\r', "\n").chars().collect();
+                self.insert(&text);
+            }
+            Key::AltEnter => self.insert(&['\n']),
+
+            Key::Right | Key::Ctrl('f') if self.can_accept_suggestion() => self.accept_suggestion(true),
+            Key::WordRight | Key::Alt('f') if self.can_accept_suggestion() => self.accept_suggestion(false),
+            Key::Left | Key::Ctrl('b') => self.cursor = self.cursor.saturating_sub(1),
+            Key::Right | Key::Ctrl('f') => self.cursor = (self.cursor + 1).min(self.buffer.len()),
+            Key::Home | Key::Ctrl('a') => self.cursor = self.line_start(),
+            Key::End | Key::Ctrl('e') => self.cursor = self.line_end(),
+            Key::WordLeft | Key::Alt('b') => self.cursor = self.word_start(),
+            Key::WordRight | Key::Alt('f') => self.cursor = self.word_end(),
+            // within multi-line input, the arrows move between its lines first
+            Key::Up if self.line_start() > 0 => self.move_vertically(false),
+            Key::Down if self.line_end() < self.buffer.len() => self.move_vertically(true),
+            Key::Up => self.history_previous(history),
+            Key::Down => self.history_next(history),
+            Key::Ctrl('r') => {
+                self.search = Some(Search {
+                    query: String::new(),
+                    index: None,
+                    original: self.buffer.clone()
+                });
+                self.update_search(history, history.entries().len());
+            }
+
+            Key::Backspace | Key::Ctrl('h') if self.cursor > 0 => {
+                self.cursor -= 1;
+                self.buffer.remove(self.cursor);
+            }
+            Key::Delete | Key::Ctrl('d') if self.cursor < self.buffer.len() => {
+                self.buffer.remove(self.cursor);
+            }
+            Key::Ctrl('w') => {
+                // up to the previous whitespace, like a shell word
+                let mut start = self.cursor;
+                while start > 0 && self.buffer[start - 1].is_whitespace() {
+                    start -= 1;
+                }
+                while start > 0 && !self.buffer[start - 1].is_whitespace() {
+                    start -= 1;
+                }
+                self.kill(start, self.cursor);
+            }
+            Key::AltBackspace => self.kill(self.word_start(), self.cursor),
+            Key::Alt('d') => self.kill(self.cursor, self.word_end()),
+            Key::Ctrl('u') => self.kill(self.line_start(), self.cursor),
+            Key::Ctrl('k') => self.kill(self.cursor, self.line_end()),
+            Key::Ctrl('y') => {
+                let text = self.kill_buffer.clone();
+                self.insert(&text);
+            }
+            // swaps the two characters before the cursor, or around it
+            Key::Ctrl('t') if self.cursor > 0 && self.buffer.len() > 1 => {
+                if self.cursor == self.buffer.len() {
+                    self.cursor -= 1;
+                }
+                self.buffer.swap(self.cursor - 1, self.cursor);
+                self.cursor += 1;
+            }
+            Key::Tab => self.complete(env),
+            Key::Ctrl('l') => {
+                write_str("\x1b[H\x1b[2J")?;
+                self.cursor_row = 0;
+            }
+            _ => ()
+        }
+
+        Ok(Action::Continue)
+    }
+
+    // suggests the rest of the newest history entry starting with the input, preferring
+    // entries run in the current directory and skipping those that would not run anymore
+    fn update_suggestion(&mut self, env: &mut Environment) {
+        self.suggestion.clear();
+        if self.buffer.is_empty() || self.cursor < self.buffer.len() || self.search.is_some() || self.menu.is_some() {
+            return;
+        }
+
+        let line: String = self.buffer.iter().collect();
+        let cwd = env::current_dir().map(|cwd| cwd.to_string_lossy().to_string()).unwrap_or_default();
+        let mut matches: Vec<(bool, String)> = env.history().entries().iter().rev()
+            .filter(|entry| entry.command.len() > line.len() && entry.command.starts_with(&line))
+            .map(|entry| (entry.cwd != cwd, entry.command.clone()))
+            .collect();
+        matches.sort_by_key(|(elsewhere, _)| *elsewhere);
+
+        let mut resolves = HashMap::new();
+        for (_, command) in matches {
+            let name = match token::tokenize_partial(&command).into_iter().next() {
+                Some((_, tok)) if tok.kind() == TokenKind::GENERIC => tok.value().clone(),
+                _ => String::new()
+            };
+
+            let valid = *resolves.entry(name.clone()).or_insert_with(|| {
+                name.is_empty() || highlight::is_keyword(&name) || highlight::is_command(&name, env)
+            });
+            if valid {
+                self.suggestion = command[line.len()..].chars().collect();
+                return;
+            }
+        }
+    }
+
+    // replaces the abbreviation just before the cursor with its expansion; returns true if
+    // the expansion had a `--set-cursor` marker, which is where the cursor goes then
+    fn expand_abbreviation(&mut self, env: &mut Environment) -> bool {
+        if self.buffer.get(self.cursor).is_some_and(|c| !c.is_whitespace()) {
+            return false;
+        }
+
+        let mut start = self.cursor;
+        while start > 0 && !self.buffer[start - 1].is_whitespace() && !complete::WORD_SEPARATORS.contains(self.buffer[start - 1]) {
+            start -= 1;
+        }
+
+        let word: String = self.buffer[start..self.cursor].iter().collect();
+        let before: String = self.buffer[..start].iter().collect();
+        let abbreviation = match env.find_abbreviation(&word) {
+            Some(abbreviation) if abbreviation.anywhere || complete::is_command_position(&before) => abbreviation.clone(),
+            _ => return false
+        };
+
+        let mut expansion = if abbreviation.function {
+            let code = format!("{} {}", abbreviation.expansion, complete::quote(&word));
+            match shell::capture_output(&code, env) {
+                Ok((true, output)) => output.strip_suffix('\n').unwrap_or(&output).to_string(),
+                _ => return false
+            }
+        } else {
+            abbreviation.expansion
+        };
+
+        let marker = abbreviation.set_cursor.as_ref().and_then(|marker| Some((expansion.find(marker.as_str())?, marker.len())));
+        let cursor = marker.map(|(pos, len)| {
+            expansion.replace_range(pos..pos + len, "");
+            expansion[..pos].chars().count()
+        });
+
+        let length = expansion.chars().count();
+        self.buffer.splice(start..self.cursor, expansion.chars());
+        self.cursor = start + cursor.unwrap_or(length);
+        cursor.is_some()
+    }
+
+    fn can_accept_suggestion(&self) -> bool {
+        self.cursor == self.buffer.len() && !self.suggestion.is_empty()
+    }
+
+    // takes all of the suggestion, or up to the end of its first word
+    fn accept_suggestion(&mut self, whole: bool) {
+        self.buffer.append(&mut self.suggestion);
+        self.cursor = if whole { self.buffer.len() } else { self.word_end() };
+        self.buffer.truncate(self.cursor);
+    }
+
+    // shows the previous entry starting with what was typed, skipping repetitions
+    fn history_previous(&mut self, history: &History) {
+        let start = match self.history_index {
+            Some(index) => index,
+            None => {
+                self.history_prefix = self.buffer.clone();
+                history.entries().len()
+            }
+        };
+
+        let prefix: String = self.history_prefix.iter().collect();
+        let current: String = self.buffer.iter().collect();
+        let found = history.entries()[..start].iter()
+            .rposition(|entry| entry.command.starts_with(&prefix) && entry.command != current);
+
+        if let Some(index) = found {
+            self.history_index = Some(index);
+            self.set_buffer(&history.entries()[index].command);
+        }
+    }
+
+    fn history_next(&mut self, history: &History) {
+        let start = match self.history_index {
+            Some(index) => index + 1,
+            None => return
+        };
+
+        let prefix: String = self.history_prefix.iter().collect();
+        let current: String = self.buffer.iter().collect();
+        let found = history.entries()[start..].iter()
+            .position(|entry| entry.command.starts_with(&prefix) && entry.command != current);
+
+        match found {
+            Some(pos) => {
+                self.history_index = Some(start + pos);
+                self.set_buffer(&history.entries()[start + pos].command);
+            }
+            None => {
+                // back to what was typed
+                self.history_index = None;
+                self.buffer = self.history_prefix.clone();
+                self.cursor = self.buffer.len();
+            }
+        }
+    }
+
+    // returns the key if it ends the search and has to be handled as usual
+    fn handle_search_key(&mut self, key: Key, history: &History) -> Option<Key> {
+        let search = self.search.as_mut().unwrap();
+        let newest = history.entries().len();
+
+        match key {
+            Key::Char(c) => {
+                search.query.push(c);
+                self.update_search(history, newest);
+            }
+            Key::Backspace | Key::Ctrl('h') => {
+                search.query.pop();
+                self.update_search(history, newest);
+            }
+            Key::Ctrl('r') => {
+                let start = search.index.unwrap_or(newest);
+                self.update_search(history, start);
+            }
+            Key::Escape | Key::Ctrl('g') => {
+                self.buffer = self.search.take().unwrap().original;
+                self.cursor = self.buffer.len();
+                self.below.clear();
+            }
+            _ => {
+                self.search = None;
+                self.below.clear();
+                return Some(key);
+            }
+        }
+
+        None
+    }
+
+    // shows the newest entry before `start` containing the query
+    fn update_search(&mut self, history: &History, start: usize) {
+        let search = self.search.as_mut().unwrap();
+        let found = history.entries()[..start].iter()
+            .rposition(|entry| !search.query.is_empty() && entry.command.contains(&search.query));
+
+        if let Some(index) = found {
+            search.index = Some(index);
+        }
+        else if !search.query.is_empty() {
+            search.index = None;
+        }
+
+        self.below = match (search.index, search.query.is_empty()) {
+            (None, false) => format!("search: {} (no match)", search.query),
+            _ => format!("search: {}", search.query)
+        };
+
+        if let Some(index) = found {
+            let command = &history.entries()[index].command;
+            let offset = command[..command.find(&search.query).unwrap_or(0)].chars().count();
+            self.set_buffer(command);
+            self.cursor = offset;
+        }
+    }
+
+    fn complete(&mut self, env: &mut Environment) {
+        let line: String = self.buffer[..self.cursor].iter().collect();
+        let (start, candidates) = complete::complete(&line, env);
+        let typed = self.cursor - start;
+
+        match candidates.len() {
+            0 => (),
+            1 => {
+                let candidate = &candidates[0];
+                let mut text = complete::quote(&candidate.replacement);
+                if candidate.add_space {
+                    text.push(' ');
+                }
+                self.replace_word(start, &text);
+            }
+            _ => {
+                // complete as far as all candidates agree, show them once there's nothing left
+                let prefix = complete::common_prefix(&candidates);
+                if prefix.chars().count() > typed {
+                    self.replace_word(start, &prefix);
+                    return;
+                }
+
+                self.menu = Some(Menu {
+                    candidates,
+                    selected: None,
+                    start,
+                    original: self.buffer[start..self.cursor].to_vec()
+                });
+                self.update_menu();
+            }
+        }
+    }
+
+    // replaces the text between `start` and the cursor
+    fn replace_word(&mut self, start: usize, text: &str) {
+        self.buffer.splice(start..self.cursor, text.chars());
+        self.cursor = start + text.chars().count();
+    }
+
+    // returns the key if it closes the menu and has to be handled as usual
+    fn handle_menu_key(&mut self, key: Key) -> Option<Key> {
+        let menu = self.menu.as_mut().unwrap();
+        let count = menu.candidates.len();
+
+        let selected = match key {
+            Key::Tab | Key::Right => menu.selected.map_or(0, |selected| (selected + 1) % count),
+            Key::BackTab | Key::Left => menu.selected.map_or(count - 1, |selected| (selected + count - 1) % count),
+            Key::Down => menu.selected.map_or(0, |selected| (selected + 1).min(count - 1)),
+            Key::Up => menu.selected.map_or(0, |selected| selected.saturating_sub(1)),
+            Key::Escape | Key::Ctrl('g') => {
+                let menu = self.menu.take().unwrap();
+                let original: String = menu.original.iter().collect();
+                self.replace_word(menu.start, &original);
+                self.below.clear();
+                return None;
+            }
+            // Enter takes the selection without running the line yet
+            Key::Enter if menu.selected.is_some() => {
+                self.menu = None;
+                self.below.clear();
+                return None;
+            }
+            _ => {
+                self.menu = None;
+                self.below.clear();
+                return Some(key);
+            }
+        };
+
+        menu.selected = Some(selected);
+        let (start, text) = (menu.start, complete::quote(&menu.candidates[selected].replacement));
+        self.replace_word(start, &text);
+        self.update_menu();
+        None
+    }
+
+    // lays out the candidates in columns, showing the page with the selection
+    fn update_menu(&mut self) {
+        let menu = self.menu.as_ref().unwrap();
+        let labels: Vec<String> = menu.candidates.iter()
+            .map(|candidate| match candidate.description.is_empty() {
+                true => candidate.display.clone(),
+                false => format!("{}  ({})", candidate.display, candidate.description)
+            })
+            .collect();
+
+        let width = labels.iter().map(|label| label.width()).max().unwrap_or(0) + 2;
+        let columns = (self.columns / width).max(1);
+        let rows = labels.len().div_ceil(columns);
+        let page_rows = (self.rows / 2).max(3);
+
+        let selected_row = menu.selected.map_or(0, |selected| selected % rows);
+        let first_row = selected_row / page_rows * page_rows;
+        let last_row = (first_row + page_rows).min(rows);
+
+        let mut lines = Vec::new();
+        for row in first_row..last_row {
+            let mut line = String::new();
+            for column in 0..columns {
+                let index = column * rows + row;
+                let label = match labels.get(index) {
+                    Some(label) => label,
+                    None => break
+                };
+
+                let padding = " ".repeat(width - label.width());
+                if menu.selected == Some(index) {
+                    line += &format!("\x1b[7m{}\x1b[0m{}", label, padding);
+                } else {
+                    line += &format!("{}{}", label, padding);
+                }
+            }
+            lines.push(line.trim_end().to_string());
+        }
+
+        if rows > page_rows {
+            lines.push(format!("rows {}-{} of {}", first_row + 1, last_row, rows));
+        }
+        self.below = lines.join("\n");
+    }
+
+    fn set_buffer(&mut self, text: &str) {
+        self.buffer = text.chars().collect();
+        self.cursor = self.buffer.len();
+    }
+
+    fn insert(&mut self, text: &[char]) {
+        self.buffer.splice(self.cursor..self.cursor, text.iter().cloned());
+        self.cursor += text.len();
+    }
+
+    // removes the text between `start` and `end`, keeping it for Ctrl-Y
+    fn kill(&mut self, start: usize, end: usize) {
+        if start < end {
+            self.kill_buffer = self.buffer.drain(start..end).collect();
+            self.cursor = start;
+        }
+    }
+
+    fn line_start(&self) -> usize {
+        self.buffer[..self.cursor].iter().rposition(|c| *c == '\n').map_or(0, |pos| pos + 1)
+    }
+
+    fn line_end(&self) -> usize {
+        self.buffer[self.cursor..].iter().position(|c| *c == '\n').map_or(self.buffer.len(), |pos| self.cursor + pos)
+    }
+
+    fn word_start(&self) -> usize {
+        let mut pos = self.cursor;
+        while pos > 0 && !self.buffer[pos - 1].is_alphanumeric() {
+            pos -= 1;
+        }
+        while pos > 0 && self.buffer[pos - 1].is_alphanumeric() {
+            pos -= 1;
+        }
+        pos
+    }
+
+    fn word_end(&self) -> usize {
+        let mut pos = self.cursor;
+        while pos < self.buffer.len() && !self.buffer[pos].is_alphanumeric() {
+            pos += 1;
+        }
+        while pos < self.buffer.len() && self.buffer[pos].is_alphanumeric() {
+            pos += 1;
+        }
+        pos
+    }
+
+    // moves between the lines of a multi-line input, keeping the column if possible
+    fn move_vertically(&mut self, down: bool) {
+        let start = self.line_start();
+        let column = self.cursor - start;
+
+        if down {
+            let end = self.line_end();
+            if end < self.buffer.len() {
+                self.cursor = end + 1;
+                self.cursor = (end + 1 + column).min(self.line_end());
+            }
+        }
+        else if start > 0 {
+            self.cursor = start - 1;
+            self.cursor = (self.line_start() + column).min(start - 1);
+        }
+    }
+
+    fn resize(&mut self, env: &mut Environment) -> io::Result<()> {
+        // the terminal has rewrapped the lines already drawn
+        (self.columns, self.rows) = terminal_size();
+        if self.menu.is_some() {
+            self.update_menu();
+        }
+        let before_cursor: String = self.buffer[..self.cursor].iter().collect();
+        let before_cursor = self.with_continuation_prompts(&before_cursor);
+        self.cursor_row = layout(&(self.prompt.clone() + &before_cursor), self.columns).0;
+        self.redraw(env)
+    }
+
+    fn redraw(&mut self, env: &mut Environment) -> io::Result<()> {
+        let mut out = String::new();
+
+        // go back to the first row of the prompt and draw everything again
+        if self.cursor_row > 0 {
+            out += &format!("\x1b[{}A", self.cursor_row);
+        }
+        out += "\r\x1b[J";
+
+        let line: String = self.buffer.iter().collect();
+        let mut text = highlight::highlight(&line, env);
+        if !self.suggestion.is_empty() {
+            let suggestion: String = self.suggestion.iter().collect();
+            text += &format!("{}{}\x1b[0m", highlight::color("autosuggestion", env), suggestion);
+        }
+        let mut text = self.with_continuation_prompts(&text);
+        let before_cursor: String = self.buffer[..self.cursor].iter().collect();
+        let before_cursor = self.with_continuation_prompts(&before_cursor);
+        if !self.below.is_empty() {
+            text = text + "\n" + &self.below;
+        }
+        out += &self.prompt;
+
+        // the right prompt goes at the end of the first row, if the input leaves room for it
+        if !self.right_prompt.is_empty() {
+            let (prompt_row, prompt_column) = layout(&self.prompt, self.columns);
+            let first_line = text.split('\n').next().unwrap_or_default();
+            let (row, column) = layout(&(self.prompt.clone() + first_line), self.columns);
+            let (right_rows, width) = layout(&self.right_prompt, usize::MAX);
+
+            if right_rows == 0 && row == prompt_row && column + width < self.columns {
+                out += &format!("\x1b[{}C{}\r", self.columns - width - prompt_column, self.right_prompt);
+                if prompt_column > 0 {
+                    out += &format!("\x1b[{}C", prompt_column);
+                }
+            }
+        }
+        out += &text;
+
+        let (mut end_row, end_column) = layout(&(self.prompt.clone() + &text), self.columns);
+        let (cursor_row, cursor_column) = layout(&(self.prompt.clone() + &before_cursor), self.columns);
+
+        // a full last row leaves the terminal's cursor in its last column
+        if end_column >= self.columns {
+            out += "\r\n";
+            end_row += 1;
+        }
+
+        let (cursor_row, cursor_column) = if cursor_column >= self.columns {
+            (cursor_row + 1, 0)
+        } else {
+            (cursor_row, cursor_column)
+        };
+
+        if end_row > cursor_row {
+            out += &format!("\x1b[{}A", end_row - cursor_row);
+        }
+        out += "\r";
+        if cursor_column > 0 {
+            out += &format!("\x1b[{}C", cursor_column);
+        }
+
+        self.cursor_row = cursor_row;
+        write_str(&out)
+    }
+
+    // the input as drawn, with the continuation prompt at the start of each further line
+    fn with_continuation_prompts(&self, text: &str) -> String {
+        text.replace('\n', &format!("\n{}", self.continuation_prompt))
+    }
+
+    // shows the submitted line after the compact transient prompt, if there is one
+    fn use_transient_prompt(&mut self) {
+        if let Some(prompt) = self.transient_prompt.take() {
+            self.prompt = prompt;
+            self.right_prompt.clear();
+        }
+    }
+
+    // leaves the cursor below the input, so that output starts on a fresh line
+    fn finish(&mut self, env: &mut Environment, end: &str) -> io::Result<()> {
+        self.cursor = self.buffer.len();
+        self.suggestion.clear();
+        self.below.clear();
+        self.redraw(env)?;
+        write_str(end)?;
+        self.cursor_row = 0;
+        Ok(())
+    }
+}
+
+fn read_plain_lines(prompt: &Prompt) -> io::Result<Input> {
+    let mut input = String::new();
+    loop {
+        write_str(if input.is_empty() { &prompt.left } else { &prompt.continuation })?;
+
+        // input ending in the middle runs into the syntax error
+        if io::stdin().lock().read_line(&mut input)? == 0 {
+            return Ok(if input.is_empty() { Input::Eof } else { Input::Line(input) });
+        }
+        if !shell::is_incomplete(&input) {
+            return Ok(Input::Line(input));
+        }
+    }
+}
+
+fn write_str(text: &str) -> io::Result<()> {
+    let mut stdout = io::stdout();
+    stdout.write_all(text.as_bytes())?;
+    stdout.flush()
+}
+
+// columns and rows of the terminal
+fn terminal_size() -> (usize, usize) {
+    let mut size: libc::winsize = unsafe { mem::zeroed() };
+    if unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) } < 0 || size.ws_col == 0 {
+        return (80, 24);
+    }
+    (size.ws_col as usize, (size.ws_row as usize).max(1))
+}
+
+// the row and column after printing `text`, skipping ANSI escape sequences; a column
+// equal to `columns` means that the terminal has yet to wrap
+fn layout(text: &str, columns: usize) -> (usize, usize) {
+    let (mut row, mut column) = (0, 0);
+    let mut chars = text.chars();
+
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            if chars.next() == Some('[') {
+                for c in chars.by_ref() {
+                    if ('@'..='~').contains(&c) {
+                        break;
+                    }
+                }
+            }
+            continue;
+        }
+
+        if c == '\n' {
+            row += 1;
+            column = 0;
+            continue;
+        }
+
+        let width = c.width().unwrap_or(0);
+        if column + width > columns {
+            row += 1;
+            column = 0;
+        }
+        column += width;
+    }
+
+    (row, column)
+}
+
+// `None` if the read was interrupted by a signal, an `UnexpectedEof` error at the end of
+// the input
+fn read_byte() -> io::Result<Option<u8>> {
+    // poll(2) is never restarted after a signal, unlike read(2)
+    let mut fd = libc::pollfd {
+        fd: libc::STDIN_FILENO,
+        events: libc::POLLIN,
+        revents: 0
+    };
+    let mut byte = 0u8;
+    let result = unsafe {
+        match libc::poll(&mut fd, 1, -1) {
+            ready if ready < 0 => ready as isize,
+            _ => libc::read(libc::STDIN_FILENO, &mut byte as *mut u8 as *mut libc::c_void, 1)
+        }
+    };
+
+    if result < 0 {
+        let err = io::Error::last_os_error();
+        if err.kind() == io::ErrorKind::Interrupted {
+            return Ok(None);
+        }
+        return Err(err);
+    }
+    else if result == 0 {
+        // the terminal is gone
+        return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+    }
+    Ok(Some(byte))
+}
+
+// whether more input arrives within a short time, to tell Escape from escape sequences
+fn input_pending() -> bool {
+    let mut fd = libc::pollfd {
+        fd: libc::STDIN_FILENO,
+        events: libc::POLLIN,
+        revents: 0
+    };
+    unsafe { libc::poll(&mut fd, 1, 50) > 0 }
+}
+
+fn read_key() -> io::Result<Option<Key>> {
+    let byte = match read_byte()? {
+        Some(byte) => byte,
+        None => return Ok(None)
+    };
+
+    let key = match byte {
+        b'\r' | b'\n' => Key::Enter,
+        b'\t' => Key::Tab,
+        0x7f => Key::Backspace,
+        0x1b => read_escape()?,
+        1..=26 => Key::Ctrl((b'a' + byte - 1) as char),
+        0..=31 => Key::Unknown,
+        _ => match read_utf8(byte)? {
+            Some(c) => Key::Char(c),
+            None => Key::Unknown
+        }
+    };
+
+    Ok(Some(key))
+}
+
+fn read_utf8(first: u8) -> io::Result<Option<char>> {
+    let len = match first {
+        0xc0..=0xdf => 2,
+        0xe0..=0xef => 3,
+        0xf0..=0xf7 => 4,
+        _ => 1
+    };
+
+    let mut bytes = vec![first];
+    while bytes.len() < len {
+        match read_byte()? {
+            Some(byte) => bytes.push(byte),
+            None => continue
+        }
+    }
+
+    Ok(std::str::from_utf8(&bytes).ok().and_then(|text| text.chars().next()))
+}
+
+fn read_escape() -> io::Result<Key> {
+    if !input_pending() {
+        return Ok(Key::Escape);
+    }
+
+    let byte = match read_byte()? {
+        Some(byte) => byte,
+        None => return Ok(Key::Escape)
+    };
+
+    match byte {
+        b'[' | b'O' => (),
+        b'\r' => return Ok(Key::AltEnter),
+        0x7f | 0x08 => return Ok(Key::AltBackspace),
+        _ => {
+            return Ok(match read_utf8(byte)? {
+                Some(c) => Key::Alt(c.to_ascii_lowercase()),
+                None => Key::Unknown
+            });
+        }
+    }
+
+    // a control sequence: parameters followed by a final byte
+    let mut sequence = String::new();
+    loop {
+        match read_byte()? {
+            Some(next) => {
+                sequence.push(next as char);
+                if (0x40..=0x7e).contains(&next) {
+                    break;
+                }
+            }
+            None => continue
+        }
+    }
+
+    Ok(match sequence.as_str() {
+        "A" => Key::Up,
+        "B" => Key::Down,
+        "C" => Key::Right,
+        "D" => Key::Left,
+        "H" | "1~" | "7~" => Key::Home,
+        "F" | "4~" | "8~" => Key::End,
+        "3~" => Key::Delete,
+        "1;5C" | "1;3C" => Key::WordRight,
+        "1;5D" | "1;3D" => Key::WordLeft,
+        "Z" => Key::BackTab,
+        "200~" => Key::Paste(read_paste()?),
+        _ => Key::Unknown
+    })
+}
+
+// reads bracketed paste until its closing sequence
+fn read_paste() -> io::Result<String> {
+    const END: &[u8] = b"\x1b[201~";
+    let mut bytes = Vec::new();
+
+    while !bytes.ends_with(END) {
+        if let Some(byte) = read_byte()? {
+            bytes.push(byte);
+        }
+    }
+
+    bytes.truncate(bytes.len() - END.len());
+    Ok(String::from_utf8_lossy(&bytes).to_string())
+}
@@ -1,31 +1,98 @@
 use std::{
     collections::HashMap,
-    env::Vars
+    env::{
+        self,
+        Vars
+    },
+    fmt,
+    io,
+    mem,
+    path::PathBuf,
+    process
 };
 
-use crate::command::Command;
+use crate::{
+    command::Command,
+    command_cache::CommandCache,
+    complete::{
+        quote,
+        Completions
+    },
+    history::History,
+    job::JobTable,
+    shell::Error,
+    universal::UniversalVars
+};
 
 pub struct Variable {
-    value: String
+    value: String,
+    read_only: bool
 }
 
 impl Variable {
     pub fn value(&self) -> String {
         self.value.clone()
     }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
 }
 
 #[derive(Clone)]
 pub enum Identifier {
     Alias {
         substitute: Command
+    },
+    Function {
+        body: Vec<Command>
+    }
+}
+
+// expanded by the line editor when followed by a space or Enter, unlike an alias
+#[derive(Debug, Clone)]
+pub struct Abbreviation {
+    pub name: String,
+    pub expansion: String,          // the text, or the function printing it with `--function`
+    pub function: bool,
+    pub anywhere: bool,             // expands in any position, not only as a command
+    pub set_cursor: Option<String>  // marker in the expansion where the cursor goes
+}
+
+impl fmt::Display for Abbreviation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "abbr -a")?;
+        if self.anywhere {
+            write!(f, " --position anywhere")?;
+        }
+        if let Some(marker) = &self.set_cursor {
+            write!(f, " --set-cursor={}", quote(marker))?;
+        }
+        if self.function {
+            write!(f, " --function {}", quote(&self.expansion))?;
+            return write!(f, " -- {}", quote(&self.name));
+        }
+        write!(f, " -- {} {}", quote(&self.name), quote(&self.expansion))
     }
 }
 
 pub struct Environment {
     variables: HashMap<String, Variable>,     // $variables
     identifiers: HashMap<String, Identifier>, // "normal" names
+    abbreviations: HashMap<String, Abbreviation>,
     outer: Option<Box<Environment>>,
+    universal: Option<UniversalVars>,
+    command_cache: CommandCache,
+    expanding_aliases: Vec<String>,
+    jobs: JobTable,
+    history: History,
+    completions: Completions,
+    traps: HashMap<String, String>, // `trap` commands by signal name
+    running_trap: bool,
+    child_niceness: Option<i32>, // set while running a command with `nice`
+    keep_redirections: bool,
+    login: bool,
+    interactive: bool
 }
 
 impl Environment {
@@ -33,7 +100,20 @@ impl Environment {
         Self {
             variables: HashMap::new(),
             identifiers: HashMap::new(),
-            outer: outer
+            abbreviations: HashMap::new(),
+            outer,
+            universal: None,
+            command_cache: CommandCache::new(),
+            expanding_aliases: Vec::new(),
+            jobs: JobTable::new(),
+            history: History::new(),
+            completions: Completions::new(),
+            traps: HashMap::new(),
+            running_trap: false,
+            child_niceness: None,
+            keep_redirections: false,
+            login: false,
+            interactive: false
         }
     }
 
@@ -41,14 +121,181 @@ impl Environment {
         let mut env = Self::new_empty(None);
         
         for (key, value) in vars.into_iter() {
-            env.add_var(key, value);
+            env.variables.insert(key, Variable { value, read_only: false });
+        }
+
+        // variables inherited from the environment take precedence
+        let universal = UniversalVars::load();
+        for (name, value) in universal.values() {
+            env.variables.entry(name.clone()).or_insert_with(|| Variable { value: value.clone(), read_only: false });
+        }
+        env.universal = Some(universal);
+
+        // variables maintained by the shell itself
+        env.set_internal_var("$", process::id().to_string());
+        env.set_internal_var("status", String::from("0"));
+        if let Ok(cwd) = env::current_dir() {
+            env.set_internal_var("PWD", cwd.to_string_lossy().to_string());
         }
         
         env
     }
 
-    pub fn add_var(&mut self, name: String, value: String) {
-        self.variables.insert(name, Variable { value });
+    // moves the current variables and identifiers into an outer scope,
+    // shell-wide state stays with the innermost environment
+    pub fn push_scope(&mut self) {
+        let mut outer = Self::new_empty(self.outer.take());
+        outer.variables = mem::take(&mut self.variables);
+        outer.identifiers = mem::take(&mut self.identifiers);
+        self.outer = Some(Box::new(outer));
+    }
+
+    pub fn pop_scope(&mut self) {
+        if let Some(outer) = self.outer.take() {
+            let outer = *outer;
+            self.variables = outer.variables;
+            self.identifiers = outer.identifiers;
+            self.outer = outer.outer;
+        }
+    }
+
+    pub fn add_var(&mut self, name: String, value: String) -> Result<(), Error> {
+        // read-only variables can neither be overwritten nor shadowed by inner scopes
+        if self.find_var(&name).is_some_and(|var| var.is_read_only()) {
+            return Err(Error::ReadOnlyVariable(name));
+        }
+
+        self.variables.insert(name, Variable { value, read_only: false });
+        Ok(())
+    }
+
+    // `export` sets the variable of the whole shell, so it outlives the function scope
+    // it was run in; shadowing function variables of the same name are dropped
+    pub fn export_var(&mut self, name: String, value: String) -> Result<(), Error> {
+        if self.find_var(&name).is_some_and(|var| var.is_read_only()) {
+            return Err(Error::ReadOnlyVariable(name));
+        }
+
+        match &mut self.outer {
+            Some(outer) => {
+                self.variables.remove(&name);
+                outer.export_var(name, value)
+            }
+            None => {
+                self.variables.insert(name, Variable { value, read_only: false });
+                Ok(())
+            }
+        }
+    }
+
+    pub fn add_const(&mut self, name: String, value: String) -> Result<(), Error> {
+        self.add_var(name.clone(), value)?;
+        self.variables.get_mut(&name).unwrap().read_only = true;
+        Ok(())
+    }
+
+    pub fn make_read_only(&mut self, name: &String) {
+        if let Some(var) = self.variables.get_mut(name) {
+            var.read_only = true;
+        }
+        else if let Some(outer) = &mut self.outer {
+            outer.make_read_only(name)
+        }
+        else {
+            self.variables.insert(name.clone(), Variable { value: String::new(), read_only: true });
+        }
+    }
+
+    // updates variables like `$status` or `PWD`, which are read-only for the user
+    pub fn set_internal_var(&mut self, name: &str, value: String) {
+        if let Some(var) = self.variables.get_mut(name) {
+            var.value = value;
+            var.read_only = true;
+        }
+        else if let Some(outer) = &mut self.outer {
+            outer.set_internal_var(name, value)
+        }
+        else {
+            self.variables.insert(name.to_string(), Variable { value, read_only: true });
+        }
+    }
+
+    pub fn remove_var(&mut self, name: &String) -> Result<bool, Error> {
+        match self.variables.get(name) {
+            Some(var) if var.is_read_only() => Err(Error::ReadOnlyVariable(name.clone())),
+            Some(_) => {
+                self.variables.remove(name);
+                Ok(true)
+            }
+            None => match &mut self.outer {
+                Some(outer) => outer.remove_var(name),
+                None => Ok(false)
+            }
+        }
+    }
+
+    pub fn set_universal(&mut self, name: String, value: String) -> Result<io::Result<()>, Error> {
+        self.add_var(name.clone(), value.clone())?;
+        Ok(match &mut self.universal {
+            Some(universal) => universal.set(name, value),
+            None => Ok(())
+        })
+    }
+
+    pub fn remove_universal(&mut self, name: &String) -> Result<io::Result<bool>, Error> {
+        self.remove_var(name)?;
+        Ok(match &mut self.universal {
+            Some(universal) => universal.remove(name),
+            None => Ok(false)
+        })
+    }
+
+    // applies changes other sheesh instances made to the universal variables
+    pub fn sync_universal(&mut self) {
+        let universal = match &mut self.universal {
+            Some(universal) => universal,
+            None => return
+        };
+
+        let previous = universal.values().clone();
+        if !universal.reload() {
+            return;
+        }
+
+        // only variables still holding their universal value follow the change, so
+        // inherited and explicitly set variables keep shadowing the universal ones
+        let current = universal.values().clone();
+        for (name, value) in current.iter() {
+            let shadowed = match self.variables.get(name) {
+                Some(var) => previous.get(name) != Some(&var.value),
+                None => false
+            };
+            if previous.get(name) != Some(value) && !shadowed {
+                self.add_var(name.clone(), value.clone()).ok();
+            }
+        }
+
+        for (name, value) in previous.iter() {
+            let unchanged = self.variables.get(name).is_some_and(|var| &var.value == value);
+            if !current.contains_key(name) && unchanged {
+                self.remove_var(name).ok();
+            }
+        }
+    }
+
+    pub fn variables(&self) -> Vec<(&String, &Variable)> {
+        let mut vars: Vec<(&String, &Variable)> = self.variables.iter().collect();
+
+        if let Some(outer) = &self.outer {
+            for (name, var) in outer.variables() {
+                if !self.variables.contains_key(name) {
+                    vars.push((name, var));
+                }
+            }
+        }
+
+        vars.sort_by(|a, b| a.0.cmp(b.0));
+        vars
     }
 
     pub fn find_var(&self, name: &String) -> Option<&Variable> {
@@ -63,6 +310,12 @@ impl Environment {
         }
     }
 
+    pub fn read_only_vars(&self) -> Vec<(&String, &Variable)> {
+        self.variables().into_iter()
+            .filter(|(_, var)| var.is_read_only())
+            .collect()
+    }
+
     pub fn add_ident(&mut self, name: String, obj: Identifier) {
         self.identifiers.insert(name, obj);
     }
@@ -78,4 +331,175 @@ impl Environment {
             None
         }
     }
-}
\ No newline at end of file
+
+    pub fn remove_alias(&mut self, name: &String) -> bool {
+        if let Some(Identifier::Alias { .. }) = self.identifiers.get(name) {
+            self.identifiers.remove(name);
+            true
+        }
+        else if let Some(outer) = &mut self.outer {
+            outer.remove_alias(name)
+        }
+        else {
+            false
+        }
+    }
+
+    pub fn aliases(&self) -> Vec<(String, Command)> {
+        let mut aliases = match &self.outer {
+            Some(outer) => outer.aliases(),
+            None => Vec::new()
+        };
+
+        for (name, ident) in self.identifiers.iter() {
+            if let Identifier::Alias { substitute } = ident {
+                aliases.retain(|(outer_name, _)| outer_name != name);
+                aliases.push((name.clone(), substitute.clone()));
+            }
+        }
+
+        aliases.sort_by(|a, b| a.0.cmp(&b.0));
+        aliases
+    }
+
+    pub fn add_abbreviation(&mut self, abbreviation: Abbreviation) {
+        self.abbreviations.insert(abbreviation.name.clone(), abbreviation);
+    }
+
+    pub fn remove_abbreviation(&mut self, name: &str) -> bool {
+        self.abbreviations.remove(name).is_some()
+    }
+
+    pub fn find_abbreviation(&self, name: &str) -> Option<&Abbreviation> {
+        self.abbreviations.get(name)
+    }
+
+    pub fn abbreviations(&self) -> Vec<&Abbreviation> {
+        let mut abbreviations: Vec<&Abbreviation> = self.abbreviations.values().collect();
+        abbreviations.sort_by(|a, b| a.name.cmp(&b.name));
+        abbreviations
+    }
+
+    pub fn functions(&self) -> Vec<String> {
+        let mut functions = match &self.outer {
+            Some(outer) => outer.functions(),
+            None => Vec::new()
+        };
+
+        for (name, ident) in self.identifiers.iter() {
+            if let Identifier::Function { .. } = ident {
+                if !functions.contains(name) {
+                    functions.push(name.clone());
+                }
+            }
+        }
+
+        functions.sort();
+        functions
+    }
+
+    // aliases currently being expanded are skipped, so that `alias ls = ls -al`
+    // falls through to the actual `ls` instead of expanding itself forever
+    pub fn is_expanding_alias(&self, name: &String) -> bool {
+        self.expanding_aliases.contains(name)
+    }
+
+    pub fn begin_alias_expansion(&mut self, name: String) {
+        self.expanding_aliases.push(name);
+    }
+
+    pub fn end_alias_expansion(&mut self) {
+        self.expanding_aliases.pop();
+    }
+
+    pub fn find_command(&mut self, name: &String) -> Option<PathBuf> {
+        let path = self.find_var(&String::from("PATH")).map(|var| var.value()).unwrap_or_default();
+        self.command_cache.lookup(name, &path)
+    }
+
+    pub fn rehash(&mut self) {
+        self.command_cache.clear();
+    }
+
+    pub fn jobs(&self) -> &JobTable {
+        &self.jobs
+    }
+
+    pub fn jobs_mut(&mut self) -> &mut JobTable {
+        &mut self.jobs
+    }
+
+    pub fn history(&self) -> &History {
+        &self.history
+    }
+
+    pub fn history_mut(&mut self) -> &mut History {
+        &mut self.history
+    }
+
+    pub fn completions(&self) -> &Completions {
+        &self.completions
+    }
+
+    pub fn completions_mut(&mut self) -> &mut Completions {
+        &mut self.completions
+    }
+
+    pub fn set_trap(&mut self, name: String, source: String) {
+        self.traps.insert(name, source);
+    }
+
+    pub fn remove_trap(&mut self, name: &str) {
+        self.traps.remove(name);
+    }
+
+    pub fn find_trap(&self, name: &str) -> Option<&String> {
+        self.traps.get(name)
+    }
+
+    pub fn traps(&self) -> Vec<(&String, &String)> {
+        let mut traps: Vec<(&String, &String)> = self.traps.iter().collect();
+        traps.sort();
+        traps
+    }
+
+    pub fn is_running_trap(&self) -> bool {
+        self.running_trap
+    }
+
+    pub fn set_running_trap(&mut self, running: bool) {
+        self.running_trap = running;
+    }
+
+    pub fn child_niceness(&self) -> Option<i32> {
+        self.child_niceness
+    }
+
+    pub fn set_child_niceness(&mut self, niceness: Option<i32>) {
+        self.child_niceness = niceness;
+    }
+
+    pub fn keep_redirections(&mut self) {
+        self.keep_redirections = true;
+    }
+
+    pub fn take_keep_redirections(&mut self) -> bool {
+        mem::take(&mut self.keep_redirections)
+    }
+
+    pub fn is_login(&self) -> bool {
+        self.login
+    }
+
+    pub fn set_login(&mut self, login: bool) {
+        self.login = login;
+    }
+
+    pub fn is_interactive(&self) -> bool {
+        self.interactive
+    }
+
+    pub fn set_interactive(&mut self, interactive: bool) {
+        self.interactive = interactive;
+    }
+}
@@ -3,54 +3,135 @@ use crate::{
         Command, 
         Value
     },
-    shell::Error,
+    shell::{
+        self,
+        Error
+    },
     environment::{
         Environment, 
         Identifier
     },
-    builtin::*
+    builtin::*,
+    job,
+    signal,
+    resource,
+    redirect,
+    token
 };
 
-use std::slice::Iter;
-use subprocess::{
-    PopenError,
-    Exec,
-    ExitStatus::*
+use std::{
+    io,
+    os::unix::process::CommandExt,
+    path::Path,
+    process,
+    slice::Iter
 };
 
-fn popen_run_process(callee: &String, args: &[String], in_background: bool) -> Result<i32, PopenError> {
-    let exec = if in_background { 
-        Exec::cmd(callee).args(args).detached() 
-    } else { 
-        Exec::cmd(callee).args(args) 
-    };
+// processes are reaped by the job table, dropping the `Child` never waits for them
+pub fn spawn_process(callee: &str, executable: &Path, args: &[String], setpgid: bool, niceness: Option<i32>) -> io::Result<process::Child> {
+    process_command(callee, executable, args, setpgid, niceness).spawn()
+}
+
+pub fn process_command(callee: &str, executable: &Path, args: &[String], setpgid: bool, niceness: Option<i32>) -> process::Command {
+    let mut command = process::Command::new(executable);
+    command.arg0(callee).args(args);
 
-    let exit_status = exec.join()?;
+    if setpgid {
+        command.process_group(0);
+    }
 
-    match exit_status {
-        Exited(code) => Ok(code as i32),
-        Signaled(sig) => {
-            eprintln!("sheesh: {} in `{}`", sig.to_string(), callee);
-            Ok(sig as i32)
+    // the niceness has to be in place before the program starts running; like nice(1),
+    // the program still runs if it cannot be set
+    if let Some(niceness) = niceness {
+        unsafe {
+            command.pre_exec(move || {
+                if resource::set_niceness(0, niceness).is_err() {
+                    let message = b"nice: cannot set niceness\n";
+                    libc::write(libc::STDERR_FILENO, message.as_ptr() as *const libc::c_void, message.len());
+                }
+                Ok(())
+            });
         }
-        Other(code) => Ok(code),
-        Undetermined => Ok(0)
     }
+    command
 }
 
 impl Command {
     pub fn eval(&self, commands: &mut Iter<Command>, env: &mut Environment) -> Result<i32, Error> {
+        // an alias is expanded before anything is applied, so its command takes over
+        // the arguments, redirections and `&`
+        let evaluated = self.callee.eval(env)?;
+        if let Some(Identifier::Alias { mut substitute }) = env.find_ident(&evaluated).cloned() {
+            if !env.is_expanding_alias(&evaluated) {
+                env.begin_alias_expansion(evaluated);
+                let result = substitute.combine(self).eval(commands, env);
+                env.end_alias_expansion();
+                return result;
+            }
+        }
+
+        // `time` leaves its redirections and `&` to the command it runs
+        if evaluated == "time" {
+            let exit_code = self.eval_time(commands, env)?;
+            env.set_internal_var("status", exit_code.to_string());
+            return Ok(exit_code);
+        }
+
+        let saved_fds = match redirect::apply(&self.redirects, env)? {
+            Ok(saved_fds) => saved_fds,
+            Err(err) => {
+                eprintln!("sheesh: {}", err);
+                env.set_internal_var("status", String::from("1"));
+                return Ok(1);
+            }
+        };
+
+        let result = self.eval_redirected(commands, env);
+
+        // `exec` without a command keeps its redirections for the rest of the shell
+        if env.take_keep_redirections() {
+            saved_fds.keep();
+        }
+        else {
+            saved_fds.restore();
+        }
+
+        result
+    }
+
+    fn eval_redirected(&self, commands: &mut Iter<Command>, env: &mut Environment) -> Result<i32, Error> {
         let evaluated = self.callee.eval(env)?;
 
-        if let Some(eval_builtin) = BUILTINS.get(&evaluated) {
+        let is_alias = matches!(env.find_ident(&evaluated), Some(Identifier::Alias { .. }));
+        let is_function = env.find_ident(&evaluated).is_some() && !is_alias;
+        let builtin = BUILTINS.get(&evaluated);
+
+        // only programs get a process of their own to run in the background
+        let result = if self.is_in_background() && (builtin.is_some() || is_function) {
+            eprintln!("sheesh: {}: builtins and functions cannot run in the background", evaluated);
+            Ok(1)
+        }
+        else if let Some(eval_builtin) = builtin {
             eval_builtin(self, commands, env)
         }
-        else if let Some(_) = env.find_ident(&evaluated) {
-            self.eval_ident_call(commands, env)
+        else if is_function {
+            self.eval_function_call(env)
         }
         else {
             self.run_program(&evaluated, env) // run an external program, like ls, grep, awk, etc.
-        }
+        };
+
+        // a failed assignment is an ordinary command failure, not a reason to stop the script
+        let exit_code = match result {
+            Err(err @ Error::ReadOnlyVariable(_)) => {
+                eprintln!("sheesh: {}", err);
+                1
+            }
+            result => result?
+        };
+
+        env.set_internal_var("status", exit_code.to_string());
+        Ok(exit_code)
     }
 
     fn run_program(&self, name: &String, env: &mut Environment) -> Result<i32, Error> {
@@ -60,41 +141,63 @@ impl Command {
             args.push(arg.eval(env)?)
         }
 
-        match popen_run_process(name, &args, self.is_in_background()) {
-            Ok(exit_code) => Ok(exit_code),
-            Err(pope_err) => Err(Error::CommandNotFound(pope_err.to_string()))
+        let executable = match env.find_command(name) {
+            Some(executable) => executable,
+            None => return Err(Error::CommandNotFound(name.clone()))
+        };
+
+        let job_control = env.jobs().has_job_control();
+        let process = match spawn_process(name, &executable, &args, job_control, env.child_niceness()) {
+            Ok(process) => process,
+            Err(err) => return Err(Error::CommandNotFound(err.to_string()))
+        };
+
+        let pid = process.id();
+        if job_control {
+            job::set_process_group(pid);
         }
+
+        if self.is_in_background() {
+            env.jobs_mut().add(pid, self.to_string());
+            env.set_internal_var("!", pid.to_string());
+            return Ok(0);
+        }
+
+        Ok(env.jobs_mut().foreground(pid, self.to_string()))
     }
 
     pub fn from_args(args: &[Value], _env: &mut Environment) -> Result<Self, Error> {
         Ok(Self {
             callee: args[0].clone(),
             args: args[1..].to_vec(),
+            redirects: Vec::new(),
             run_in_bg: false
         })
     }
 
-    fn eval_ident_call(&self, commands: &mut Iter<Command>, env: &mut Environment) -> Result<i32, Error> {
+    fn eval_function_call(&self, env: &mut Environment) -> Result<i32, Error> {
         let evaluated = self.callee.eval(env)?;
-        let id = env.find_ident(&evaluated).unwrap();
+        let body = match env.find_ident(&evaluated) {
+            Some(Identifier::Function { body }) => body.clone(),
+            _ => return Err(Error::CommandNotFound(evaluated))
+        };
 
-        match id.clone() {
-            Identifier::Alias { mut substitute } => {
-                substitute.combine(self).eval(commands, env)
-            },
+        let mut args = Vec::new();
+        for arg in &self.args {
+            args.push(arg.eval(env)?);
         }
+
+        env.push_scope();
+        let result = eval_function(body, args, env);
+        env.pop_scope();
+        result
     }
 }
 
 impl Value {
     pub fn eval(&self, env: &mut Environment) -> Result<String, Error> {
         match self {
-            Self::String(str) => {
-                let mut chars = str.chars();
-                chars.next();
-                chars.next_back();
-                Ok(chars.collect::<String>())
-            }
+            Self::String(str) => Ok(token::unquote(str)),
             Self::PlainText(_, str) => Ok(str.to_string()),
             Self::Variable(callee) => {
                 match env.find_var(callee) {
@@ -106,12 +209,34 @@ impl Value {
     }
 }
 
+fn eval_function(body: Vec<Command>, args: Vec<String>, env: &mut Environment) -> Result<i32, Error> {
+    env.add_var(String::from("argv"), args.join(" "))?;
+    for (i, arg) in args.into_iter().enumerate() {
+        env.add_var((i + 1).to_string(), arg)?;
+    }
+
+    evaluate(body, env)
+}
+
 pub fn evaluate(commands: Vec<Command>, env: &mut Environment) -> Result<i32, Error> {
     let mut last_exit_code = 0;
     let mut iter = commands.iter();
     
     while let Some(command) = iter.next() {
         last_exit_code = command.eval(&mut iter, env)?;
+        shell::run_pending_traps(env)?;
+
+        if signal::take_interrupted() {
+            return Err(Error::Interrupted);
+        }
+
+        // blocks and function calls report the failure of the statement inside them
+        if last_exit_code != 0 && !command.starts_block() {
+            let callee = command.callee.eval(env)?;
+            if !matches!(env.find_ident(&callee), Some(Identifier::Function { .. })) {
+                shell::run_trap("ERR", env)?;
+            }
+        }
     }
 
     Ok(last_exit_code)
@@ -0,0 +1,638 @@
+use std::{
+    env,
+    fs::{
+        self,
+        File
+    },
+    io::{
+        self,
+        Read
+    },
+    path::{
+        Path,
+        PathBuf
+    },
+    process::{
+        Command,
+        Stdio
+    },
+    thread,
+    time::{
+        Duration,
+        Instant
+    }
+};
+
+use flate2::read::GzDecoder;
+
+use crate::{
+    complete::CompletionSpec,
+    shell
+};
+
+// man page sections with commands in them
+const SECTIONS: [&str; 2] = ["1", "8"];
+
+// how long a command may take to print its --help
+const HELP_TIMEOUT: Duration = Duration::from_secs(2);
+
+// `sheesh --generate-completions [command...]` writes `complete` specs for commands,
+// taken from their man pages or --help output, to `$XDG_DATA_HOME/sheesh/generated_completions/`.
+// Without commands, all man pages are used.
+pub fn generate_completions(commands: &[String]) -> i32 {
+    let dir = match generated_dir() {
+        Some(dir) => dir,
+        None => {
+            eprintln!("sheesh: neither $XDG_DATA_HOME nor $HOME is set");
+            return 1;
+        }
+    };
+
+    if let Err(err) = fs::create_dir_all(&dir) {
+        eprintln!("sheesh: {}: {}", dir.display(), err);
+        return 1;
+    }
+
+    let pages: Vec<(String, Option<PathBuf>)> = if commands.is_empty() {
+        all_man_pages().into_iter().map(|(command, page)| (command, Some(page))).collect()
+    } else {
+        commands.iter().map(|command| (command.clone(), find_man_page(command))).collect()
+    };
+
+    let mut generated = 0;
+    for (command, page) in pages {
+        let (source, options) = match &page {
+            Some(page) => (page.display().to_string(), read_man_page(page).map(|page| parse_man_page(&page))),
+            None if !commands.is_empty() => (format!("{} --help", command), read_help(&command).map(|help| parse_help(&help))),
+            None => continue
+        };
+
+        let options = match options {
+            Ok(options) if !options.is_empty() => options,
+            _ => {
+                if !commands.is_empty() {
+                    eprintln!("sheesh: {}: no options found", command);
+                }
+                continue;
+            }
+        };
+
+        let mut contents = format!("# generated by `sheesh --generate-completions` from {}\n", source);
+        for option in options {
+            let spec = CompletionSpec {
+                command: command.clone(),
+                short_options: option.short,
+                long_options: option.long,
+                description: option.description,
+                ..Default::default()
+            };
+            contents += &format!("{}\n", spec);
+        }
+
+        let path = dir.join(format!("{}.sh", command));
+        match fs::write(&path, contents) {
+            Ok(_) => generated += 1,
+            Err(err) => eprintln!("sheesh: {}: {}", path.display(), err)
+        }
+    }
+
+    println!("generated completions for {} commands in {}", generated, dir.display());
+    0
+}
+
+// looked at by the completion after `$XDG_CONFIG_HOME/sheesh/completions/`
+pub fn generated_dir() -> Option<PathBuf> {
+    shell::data_dir().map(|dir| dir.join("generated_completions"))
+}
+
+#[derive(Debug, Default)]
+struct OptionHelp {
+    short: Vec<String>,
+    long: Vec<String>,
+    description: String
+}
+
+fn man_dirs() -> Vec<PathBuf> {
+    let manpath = env::var("MANPATH").unwrap_or_default();
+    let mut dirs: Vec<PathBuf> = env::split_paths(&manpath).filter(|dir| !dir.as_os_str().is_empty()).collect();
+
+    // an empty entry, or no $MANPATH at all, stands for the default locations
+    if dirs.is_empty() || manpath.starts_with(':') || manpath.ends_with(':') || manpath.contains("::") {
+        for dir in ["/usr/local/share/man", "/usr/share/man", "/usr/man"] {
+            dirs.push(PathBuf::from(dir));
+        }
+    }
+    dirs
+}
+
+// `ls.1.gz` belongs to `ls`, `python3.11.1` to `python3.11`
+fn page_command(file_name: &str, section: &str) -> Option<String> {
+    let name = file_name.strip_suffix(".gz").unwrap_or(file_name);
+    let (command, suffix) = name.rsplit_once('.')?;
+
+    if suffix.starts_with(section) && !command.is_empty() {
+        Some(command.to_string())
+    }
+    else {
+        None
+    }
+}
+
+fn all_man_pages() -> Vec<(String, PathBuf)> {
+    let mut pages: Vec<(String, PathBuf)> = Vec::new();
+
+    for dir in man_dirs() {
+        for section in SECTIONS.iter() {
+            let entries = match fs::read_dir(dir.join(format!("man{}", section))) {
+                Ok(entries) => entries,
+                Err(_) => continue
+            };
+
+            for entry in entries.flatten() {
+                let name = entry.file_name().to_string_lossy().to_string();
+                if let Some(command) = page_command(&name, section) {
+                    // the first directory wins, like with `man`
+                    if !pages.iter().any(|(other, _)| *other == command) {
+                        pages.push((command, entry.path()));
+                    }
+                }
+            }
+        }
+    }
+
+    pages.sort();
+    pages
+}
+
+fn find_man_page(command: &str) -> Option<PathBuf> {
+    for dir in man_dirs() {
+        for section in SECTIONS.iter() {
+            let entries = match fs::read_dir(dir.join(format!("man{}", section))) {
+                Ok(entries) => entries,
+                Err(_) => continue
+            };
+
+            for entry in entries.flatten() {
+                let name = entry.file_name().to_string_lossy().to_string();
+                if page_command(&name, section).as_deref() == Some(command) {
+                    return Some(entry.path());
+                }
+            }
+        }
+    }
+    None
+}
+
+fn read_man_page(path: &Path) -> io::Result<String> {
+    let mut bytes = Vec::new();
+    let file = File::open(path)?;
+
+    if path.extension().is_some_and(|extension| extension == "gz") {
+        GzDecoder::new(file).read_to_end(&mut bytes)?;
+    }
+    else {
+        let mut file = file;
+        file.read_to_end(&mut bytes)?;
+    }
+
+    Ok(String::from_utf8_lossy(&bytes).to_string())
+}
+
+// runs `command --help`, killing it if it takes too long
+fn read_help(command: &str) -> io::Result<String> {
+    let mut child = Command::new(command)
+        .arg("--help")
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()?;
+
+    let mut stdout = child.stdout.take().unwrap();
+    let reader = thread::spawn(move || {
+        let mut output = String::new();
+        stdout.read_to_string(&mut output).ok();
+        output
+    });
+
+    let deadline = Instant::now() + HELP_TIMEOUT;
+    while child.try_wait()?.is_none() {
+        if Instant::now() >= deadline {
+            child.kill().ok();
+            child.wait().ok();
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "--help took too long"));
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    Ok(reader.join().unwrap_or_default())
+}
+
+// finds the option lists of man(7) pages (`.TP`, `.IP`) and mdoc(7) pages (`.It Fl`)
+fn parse_man_page(page: &str) -> Vec<OptionHelp> {
+    let mut options = Vec::new();
+    let mut tag: Option<String> = None;
+    let mut description = String::new();
+    let mut expecting_tag = false;
+
+    let mut finish = |tag: &mut Option<String>, description: &mut String| {
+        if let Some(tag) = tag.take() {
+            add_option(&mut options, &tag, description);
+        }
+        description.clear();
+    };
+
+    for line in page.lines() {
+        if let Some(request) = line.strip_prefix('.').or_else(|| line.strip_prefix('\'')) {
+            let (name, args) = request.trim_start().split_once(char::is_whitespace).unwrap_or((request.trim(), ""));
+
+            match name {
+                "TP" => {
+                    finish(&mut tag, &mut description);
+                    expecting_tag = true;
+                }
+                "IP" => {
+                    finish(&mut tag, &mut description);
+                    let args = split_args(args);
+                    tag = args.first().map(|first| roff_to_text(first)).filter(|text| text.starts_with('-'));
+                }
+                "It" => {
+                    finish(&mut tag, &mut description);
+                    tag = Some(mdoc_to_text(args)).filter(|text| text.starts_with('-'));
+                }
+                "TQ" | "PD" | "br" | "sp" | "RS" | "RE" | "\\\"" | "" => (),
+                "B" | "I" | "SM" | "SB" => {
+                    let text = split_args(args).iter().map(|arg| roff_to_text(arg)).collect::<Vec<String>>().join(" ");
+                    add_text(&mut tag, &mut description, &mut expecting_tag, &text);
+                }
+                "BR" | "BI" | "IB" | "IR" | "RB" | "RI" => {
+                    let text: String = split_args(args).iter().map(|arg| roff_to_text(arg)).collect();
+                    add_text(&mut tag, &mut description, &mut expecting_tag, &text);
+                }
+                "Nm" | "Fl" | "Ar" | "Op" => {
+                    add_text(&mut tag, &mut description, &mut expecting_tag, &mdoc_to_text(request));
+                }
+                _ => {
+                    // paragraphs and sections end the description
+                    finish(&mut tag, &mut description);
+                    expecting_tag = false;
+                }
+            }
+            continue;
+        }
+
+        let text = roff_to_text(line);
+        add_text(&mut tag, &mut description, &mut expecting_tag, &text);
+    }
+
+    finish(&mut tag, &mut description);
+    options
+}
+
+fn add_text(tag: &mut Option<String>, description: &mut String, expecting_tag: &mut bool, text: &str) {
+    if *expecting_tag {
+        *expecting_tag = false;
+        *tag = Some(text.trim().to_string()).filter(|text| text.starts_with('-'));
+    }
+    else if tag.is_some() {
+        if !description.is_empty() {
+            description.push(' ');
+        }
+        description.push_str(text.trim());
+    }
+}
+
+// lines like `  -a, --all        do not ignore entries starting with .`
+fn parse_help(help: &str) -> Vec<OptionHelp> {
+    let mut options = Vec::new();
+    let lines: Vec<&str> = help.lines().collect();
+
+    for (i, line) in lines.iter().enumerate() {
+        let trimmed = line.trim_start();
+        if !trimmed.starts_with('-') {
+            continue;
+        }
+
+        // the description follows after two spaces, or on the next, further indented line
+        let (tag, mut description) = match trimmed.find("  ") {
+            Some(pos) => (&trimmed[..pos], trimmed[pos..].trim().to_string()),
+            None => (trimmed, String::new())
+        };
+
+        if description.is_empty() {
+            let indent = line.len() - trimmed.len();
+            if let Some(next) = lines.get(i + 1) {
+                let next_trimmed = next.trim_start();
+                if next.len() - next_trimmed.len() > indent && !next_trimmed.starts_with('-') {
+                    description = next_trimmed.to_string();
+                }
+            }
+        }
+
+        add_option(&mut options, tag, &description);
+    }
+
+    options
+}
+
+// takes the option names from a tag like `-a, --all` or `--color[=WHEN]`
+fn add_option(options: &mut Vec<OptionHelp>, tag: &str, description: &str) {
+    let mut option = OptionHelp::default();
+
+    for word in tag.split(|c: char| c == ',' || c == '|' || c.is_whitespace()) {
+        let word = word.split(['=', '[', '<']).next().unwrap_or_default();
+
+        if let Some(long) = word.strip_prefix("--") {
+            if !long.is_empty() && long.chars().all(|c| c.is_alphanumeric() || c == '-' || c == '_') {
+                option.long.push(long.to_string());
+            }
+        }
+        else if let Some(short) = word.strip_prefix('-') {
+            // old style options like `-name` can't be expressed with `-s` or `-l`
+            if short.chars().count() == 1 && short != "-" {
+                option.short.push(short.to_string());
+            }
+        }
+    }
+
+    if option.short.is_empty() && option.long.is_empty() {
+        return;
+    }
+
+    option.description = first_sentence(description);
+    options.push(option);
+}
+
+fn first_sentence(text: &str) -> String {
+    let text = text.split_whitespace().collect::<Vec<&str>>().join(" ");
+    // a period ends the sentence only right after a word, unlike in `entries starting with .`
+    let chars: Vec<char> = text.chars().collect();
+    let end = (1..chars.len()).find(|&i| {
+        chars[i] == '.' && (chars[i - 1].is_alphanumeric() || chars[i - 1] == ')') && chars.get(i + 1).is_none_or(|c| *c == ' ')
+    });
+    let sentence: String = chars[..end.unwrap_or(chars.len())].iter().collect();
+
+    if sentence.chars().count() > 70 {
+        let short: String = sentence.chars().take(67).collect();
+        return format!("{}...", short.trim_end());
+    }
+    sentence
+}
+
+// splits request arguments, which can be put into double quotes
+fn split_args(args: &str) -> Vec<String> {
+    let mut result = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+
+    for c in args.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            c if c.is_whitespace() && !quoted => {
+                if !current.is_empty() {
+                    result.push(current.clone());
+                    current.clear();
+                }
+            }
+            c => current.push(c)
+        }
+    }
+
+    if !current.is_empty() {
+        result.push(current);
+    }
+    result
+}
+
+// `.It Fl a Ar file` becomes `-a file`, `Fl -all` becomes `--all`
+fn mdoc_to_text(args: &str) -> String {
+    let mut words = Vec::new();
+    let mut flag = false;
+
+    for word in args.split_whitespace() {
+        match word {
+            "Fl" => flag = true,
+            "Ar" | "Op" | "Oo" | "Oc" | "Nm" | "Ns" | "Xo" | "Xc" | "Pq" | "Cm" => (),
+            _ => {
+                let text = roff_to_text(word);
+                words.push(if flag { format!("-{}", text) } else { text });
+                flag = false;
+            }
+        }
+    }
+
+    // a lone `Fl` is a plain dash
+    if flag {
+        words.push(String::from("-"));
+    }
+    words.join(" ")
+}
+
+// removes font changes and other escapes from a line of text
+fn roff_to_text(line: &str) -> String {
+    let mut text = String::new();
+    let mut chars = line.chars().peekable();
+
+    // reads the name after `\(`, `\[` or a single character
+    fn escape_name(chars: &mut std::iter::Peekable<std::str::Chars>) -> String {
+        match chars.next() {
+            Some('(') => chars.by_ref().take(2).collect(),
+            Some('[') => chars.by_ref().take_while(|c| *c != ']').collect(),
+            Some(c) => c.to_string(),
+            None => String::new()
+        }
+    }
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            text.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('f') | Some('*') | Some('n') | Some('F') | Some('m') => {
+                escape_name(&mut chars);
+            }
+            Some('(') => {
+                let name: String = chars.by_ref().take(2).collect();
+                text += special_char(&name);
+            }
+            Some('[') => {
+                let name: String = chars.by_ref().take_while(|c| *c != ']').collect();
+                text += special_char(&name);
+            }
+            Some('s') => {
+                while chars.peek().is_some_and(|c| c.is_ascii_digit() || *c == '+' || *c == '-') {
+                    chars.next();
+                }
+            }
+            Some('"') => break,
+            Some('-') => text.push('-'),
+            Some('e') | Some('\\') => text.push('\\'),
+            Some(' ') | Some('~') | Some('0') => text.push(' '),
+            Some('&') | Some('|') | Some('^') | Some(',') | Some('/') | Some(')') | Some('c') => (),
+            Some(c) => text.push(c),
+            None => ()
+        }
+    }
+
+    text
+}
+
+fn special_char(name: &str) -> &'static str {
+    match name {
+        "em" | "en" | "hy" | "mi" => "-",
+        "aq" | "cq" | "oq" => "'",
+        "lq" | "rq" | "dq" => "\"",
+        "bu" => "*",
+        "ti" => "~",
+        "rs" => "\\",
+        _ => ""
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the short and long names and the description of each option
+    fn summary(options: Vec<OptionHelp>) -> Vec<(Vec<String>, Vec<String>, String)> {
+        options.into_iter().map(|option| (option.short, option.long, option.description)).collect()
+    }
+
+    fn option(short: &[&str], long: &[&str], description: &str) -> (Vec<String>, Vec<String>, String) {
+        (
+            short.iter().map(|s| s.to_string()).collect(),
+            long.iter().map(|s| s.to_string()).collect(),
+            description.to_string()
+        )
+    }
+
+    #[test]
+    fn help_with_descriptions_on_the_same_line() {
+        // from `ls --help` of GNU coreutils
+        let help = "\
+Usage: ls [OPTION]... [FILE]...
+List information about the FILEs (the current directory by default).
+
+Mandatory arguments to long options are mandatory for short options too.
+  -a, --all                  do not ignore entries starting with .
+      --author               with -l, print the author of each file
+      --block-size=SIZE      with -l, scale sizes by SIZE when printing them;
+                             e.g., '--block-size=M'; see SIZE format below
+
+  -c                         with -lt: sort by, and show, ctime (time of last
+                             modification of file status information);
+      --color[=WHEN]         color the output WHEN; more info below
+";
+
+        assert_eq!(summary(parse_help(help)), vec![
+            option(&["a"], &["all"], "do not ignore entries starting with ."),
+            option(&[], &["author"], "with -l, print the author of each file"),
+            option(&[], &["block-size"], "with -l, scale sizes by SIZE when printing them;"),
+            option(&["c"], &[], "with -lt: sort by, and show, ctime (time of last"),
+            option(&[], &["color"], "color the output WHEN; more info below")
+        ]);
+    }
+
+    #[test]
+    fn help_with_descriptions_on_the_next_line() {
+        // from `sed --help` of GNU sed
+        let help = "\
+Usage: sed [OPTION]... {script-only-if-no-other-script} [input-file]...
+
+  -n, --quiet, --silent
+                 suppress automatic printing of pattern space
+      --debug
+                 annotate program execution
+  -e script, --expression=script
+                 add the script to the commands to be executed
+  -i[SUFFIX], --in-place[=SUFFIX]
+                 edit files in place (makes backup if SUFFIX supplied)
+";
+
+        assert_eq!(summary(parse_help(help)), vec![
+            option(&["n"], &["quiet", "silent"], "suppress automatic printing of pattern space"),
+            option(&[], &["debug"], "annotate program execution"),
+            option(&["e"], &["expression"], "add the script to the commands to be executed"),
+            option(&["i"], &["in-place"], "edit files in place (makes backup if SUFFIX supplied)")
+        ]);
+    }
+
+    #[test]
+    fn man_page_with_tagged_paragraphs() {
+        // from ls(1) of GNU coreutils
+        let page = r#".SH DESCRIPTION
+.\" Add any additional description here
+.PP
+List information about the FILEs (the current directory by default).
+Sort entries alphabetically if none of \fB\-cftuvSUX\fR nor \fB\-\-sort\fR is specified.
+.TP
+\fB\-a\fR, \fB\-\-all\fR
+do not ignore entries starting with .
+.TP
+\fB\-A\fR, \fB\-\-almost\-all\fR
+do not list implied . and ..
+.TP
+\fB\-\-block\-size\fR=\fI\,SIZE\/\fR
+with \fB\-l\fR, scale sizes by SIZE when printing them;
+e.g., '\-\-block\-size=M'; see SIZE format below
+.SH AUTHOR
+"#;
+
+        assert_eq!(summary(parse_man_page(page)), vec![
+            option(&["a"], &["all"], "do not ignore entries starting with ."),
+            option(&["A"], &["almost-all"], "do not list implied . and .."),
+            option(&[], &["block-size"], "with -l, scale sizes by SIZE when printing them; e.g., '--block-siz...")
+        ]);
+    }
+
+    #[test]
+    fn man_page_with_indented_paragraphs() {
+        // from curl(1) and find(1)
+        let page = r#".IP "\-a, \-\-append"
+(FTP SFTP) When used in an upload, this makes curl append to the target file instead of
+overwriting it. If the remote file does not exist, it will be created. Note
+.IP \-P
+Never follow symbolic links.  This is the default behaviour.  When
+.B find
+examines or prints information about files, and the file is a symbolic
+.IP
+.SH EXAMPLES
+"#;
+
+        assert_eq!(summary(parse_man_page(page)), vec![
+            option(&["a"], &["append"], "(FTP SFTP) When used in an upload, this makes curl append to the ta..."),
+            option(&["P"], &[], "Never follow symbolic links")
+        ]);
+    }
+
+    #[test]
+    fn mdoc_page() {
+        // from ssh(1) of OpenSSH
+        let page = r#".Bl -tag -width Ds
+.It Fl 4
+Forces
+.Nm
+to use IPv4 addresses only.
+.Pp
+.It Fl B Ar bind_interface
+Bind to the address of
+.Ar bind_interface
+before attempting to connect to the destination host.
+This is only useful on systems with more than one address.
+.El
+"#;
+
+        assert_eq!(summary(parse_man_page(page)), vec![
+            option(&["4"], &[], "Forces to use IPv4 addresses only"),
+            option(&["B"], &[], "Bind to the address of bind_interface before attempting to connect...")
+        ]);
+    }
+
+    #[test]
+    fn first_sentence_of_a_description() {
+        assert_eq!(first_sentence("Never follow symbolic links.  This is the default behaviour."), "Never follow symbolic links");
+        assert_eq!(first_sentence("do not list implied . and .."), "do not list implied . and ..");
+        assert_eq!(first_sentence("use \"quoted\"\n  text"), "use \"quoted\" text");
+    }
+}
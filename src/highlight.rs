@@ -0,0 +1,197 @@
+use std::path::Path;
+
+use crate::{
+    builtin::BUILTINS,
+    command::BLOCK_KEYWORDS,
+    complete::PRECOMMANDS,
+    environment::Environment,
+    token::{
+        self,
+        TokenKind::*
+    }
+};
+
+// the colors used when the corresponding `color_*` variable is not set
+const DEFAULT_COLORS: [(&str, &str); 9] = [
+    ("command", "blue"),
+    ("error", "red"),
+    ("keyword", "magenta"),
+    ("quote", "yellow"),
+    ("variable", "cyan"),
+    ("operator", "green"),
+    ("comment", "brblack"),
+    ("param", "normal"),
+    ("autosuggestion", "brblack")
+];
+
+const COLOR_NAMES: [&str; 8] = ["black", "red", "green", "yellow", "blue", "magenta", "cyan", "white"];
+
+const RESET: &str = "\x1b[0m";
+const UNDERLINE: &str = "\x1b[4m";
+
+// `line` with ANSI escape sequences coloring each token by its role
+pub fn highlight(line: &str, env: &mut Environment) -> String {
+    let mut out = String::new();
+    let mut end = 0;
+    let mut command_position = true;
+
+    for (start, tok) in token::tokenize_partial(line) {
+        let value = tok.value().as_str();
+        if value.is_empty() {
+            continue;
+        }
+
+        // whitespace between the tokens
+        out += &line[end..start];
+        end = start + value.len();
+
+        // the contents of a string, which may be missing its closing quote
+        let text = match tok.kind() {
+            STRING if value.len() > 1 && value.ends_with(&value[..1]) => &value[1..value.len() - 1],
+            STRING => &value[1..],
+            _ => value
+        };
+
+        let (role, is_path) = match tok.kind() {
+            GENERIC if command_position && is_keyword(value) => {
+                command_position = value == "if";
+                ("keyword", false)
+            }
+            GENERIC | STRING if command_position && !value.starts_with('-') => {
+                command_position = PRECOMMANDS.contains(&value);
+                (if is_command(text, env) { "command" } else { "error" }, false)
+            }
+            GENERIC => ("param", is_existing_path(text)),
+            STRING => ("quote", is_existing_path(text)),
+            VARIABLE => {
+                command_position = false;
+                ("variable", false)
+            }
+            COMMENT => ("comment", false),
+            SEMICOLON | NEWLINE | BGPROCESS | AND | PIPE | OR => {
+                command_position = true;
+                ("operator", false)
+            }
+            _ => ("operator", false)
+        };
+
+        let color = color(role, env);
+        if color.is_empty() && !is_path {
+            out += value;
+            continue;
+        }
+
+        out += &color;
+        if is_path {
+            out += UNDERLINE;
+        }
+        out += value;
+        out += RESET;
+    }
+
+    out += &line[end.min(line.len())..];
+    out
+}
+
+pub fn is_keyword(word: &str) -> bool {
+    BLOCK_KEYWORDS.contains(&word) || word == "else" || word == "end"
+}
+
+// whether `name` can be run as an alias, builtin, function or program
+pub fn is_command(name: &str, env: &mut Environment) -> bool {
+    let name = name.to_string();
+    BUILTINS.contains_key(name.as_str()) || env.find_ident(&name).is_some() || env.find_command(&name).is_some()
+}
+
+fn is_existing_path(word: &str) -> bool {
+    !word.is_empty() && Path::new(word).exists()
+}
+
+// the escape sequence for a role, from `$color_<role>` or its default
+pub fn color(role: &str, env: &Environment) -> String {
+    let spec = match env.find_var(&format!("color_{}", role)) {
+        Some(var) => var.value(),
+        None => DEFAULT_COLORS.iter()
+            .find(|(name, _)| *name == role)
+            .map(|(_, spec)| spec.to_string())
+            .unwrap_or_default()
+    };
+    parse_color(&spec)
+}
+
+// a color like `red`, `brblue`, `ff8800` or `#f80`, followed by options like `--bold`
+pub fn parse_color(spec: &str) -> String {
+    let mut codes = Vec::new();
+
+    for word in spec.split_whitespace() {
+        let code = match word {
+            "-o" | "--bold" => "1".to_string(),
+            "-d" | "--dim" => "2".to_string(),
+            "-i" | "--italics" => "3".to_string(),
+            "-u" | "--underline" => "4".to_string(),
+            "-r" | "--reverse" => "7".to_string(),
+            "normal" => continue,
+            _ => match color_code(word) {
+                Some(code) => code,
+                None => continue
+            }
+        };
+        codes.push(code);
+    }
+
+    if codes.is_empty() {
+        return String::new();
+    }
+    format!("\x1b[{}m", codes.join(";"))
+}
+
+fn color_code(word: &str) -> Option<String> {
+    let (name, base) = match word.strip_prefix("br") {
+        Some(name) => (name, 90),
+        None => (word, 30)
+    };
+    if let Some(index) = COLOR_NAMES.iter().position(|color| *color == name) {
+        return Some((base + index).to_string());
+    }
+
+    let hex = word.trim_start_matches('#');
+    if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    let digits: Vec<u8> = hex.chars().map(|c| c.to_digit(16).unwrap_or(0) as u8).collect();
+    let (r, g, b) = match digits.len() {
+        3 => (digits[0] * 17, digits[1] * 17, digits[2] * 17),
+        6 => (digits[0] * 16 + digits[1], digits[2] * 16 + digits[3], digits[4] * 16 + digits[5]),
+        _ => return None
+    };
+    Some(format!("38;2;{};{};{}", r, g, b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn named_colors() {
+        assert_eq!(parse_color("red"), "\x1b[31m");
+        assert_eq!(parse_color("brblue"), "\x1b[94m");
+        assert_eq!(parse_color("normal"), "");
+    }
+
+    #[test]
+    fn hex_colors() {
+        assert_eq!(parse_color("ff8800"), "\x1b[38;2;255;136;0m");
+        assert_eq!(parse_color("#f80"), "\x1b[38;2;255;136;0m");
+        assert_eq!(parse_color("#12345"), "");
+        assert_eq!(parse_color("ggg"), "");
+    }
+
+    #[test]
+    fn options_follow_the_color() {
+        assert_eq!(parse_color("green --bold -u"), "\x1b[32;1;4m");
+        assert_eq!(parse_color("--reverse"), "\x1b[7m");
+        assert_eq!(parse_color("nosuchcolor --italics"), "\x1b[3m");
+        assert_eq!(parse_color(""), "");
+    }
+}
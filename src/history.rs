@@ -0,0 +1,295 @@
+use std::{
+    fs::{
+        self,
+        File,
+        OpenOptions
+    },
+    io::{
+        self,
+        Read,
+        Seek,
+        SeekFrom,
+        Write
+    },
+    os::unix::io::AsRawFd,
+    path::PathBuf,
+    time::{
+        SystemTime,
+        UNIX_EPOCH
+    }
+};
+
+use crate::{
+    shell,
+    universal::{
+        escape,
+        unescape
+    }
+};
+
+const FILE_NAME: &str = "history";
+
+#[derive(Debug, Clone)]
+pub struct HistoryEntry {
+    pub timestamp: u64,
+    pub command: String,
+    pub cwd: String
+}
+
+// commands entered at the REPL, stored as `timestamp<TAB>command<TAB>cwd` lines in
+// `$XDG_DATA_HOME/sheesh/history`. Every instance appends to the same file, and picks up
+// the lines the others appended whenever it writes to it itself.
+pub struct History {
+    path: Option<PathBuf>,
+    entries: Vec<HistoryEntry>,
+    read_len: u64 // how much of the file is in `entries`
+}
+
+// holds an exclusive lock on the history file while it is being read or written
+struct LockedFile {
+    file: File
+}
+
+impl LockedFile {
+    fn open(path: &PathBuf) -> io::Result<Self> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let file = OpenOptions::new().read(true).append(true).create(true).open(path)?;
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self { file })
+    }
+}
+
+impl Drop for LockedFile {
+    fn drop(&mut self) {
+        unsafe { libc::flock(self.file.as_raw_fd(), libc::LOCK_UN) };
+    }
+}
+
+impl History {
+    pub fn new() -> Self {
+        Self {
+            path: None,
+            entries: Vec::new(),
+            read_len: 0
+        }
+    }
+
+    // only the REPL keeps a history, scripts start with an empty one
+    pub fn load(&mut self) {
+        self.path = shell::data_dir().map(|dir| dir.join(FILE_NAME));
+        if let Some(mut file) = self.path.as_ref().and_then(|path| LockedFile::open(path).ok()) {
+            self.merge(&mut file).ok();
+        }
+    }
+
+    pub fn entries(&self) -> &Vec<HistoryEntry> {
+        &self.entries
+    }
+
+    // reads the lines appended by other instances since the last read
+    fn merge(&mut self, file: &mut LockedFile) -> io::Result<()> {
+        let len = file.file.metadata()?.len();
+
+        // another instance rewrote the file, so start over
+        if len < self.read_len {
+            self.entries.clear();
+            self.read_len = 0;
+        }
+
+        let mut contents = Vec::new();
+        file.file.seek(SeekFrom::Start(self.read_len))?;
+        file.file.read_to_end(&mut contents)?;
+
+        // a line without its newline is still being written
+        let complete = match contents.iter().rposition(|byte| *byte == b'\n') {
+            Some(pos) => pos + 1,
+            None => return Ok(())
+        };
+
+        for line in String::from_utf8_lossy(&contents[..complete]).lines() {
+            let mut fields = line.splitn(3, '\t');
+            let timestamp = fields.next().and_then(|field| field.parse().ok());
+            let command = fields.next();
+
+            if let (Some(timestamp), Some(command)) = (timestamp, command) {
+                self.entries.push(HistoryEntry {
+                    timestamp,
+                    command: unescape(command),
+                    cwd: fields.next().map(unescape).unwrap_or_default()
+                });
+            }
+        }
+
+        self.read_len += complete as u64;
+        Ok(())
+    }
+
+    pub fn add(&mut self, command: &str, cwd: String) -> io::Result<()> {
+        let entry = HistoryEntry {
+            timestamp: SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_secs()),
+            command: command.to_string(),
+            cwd
+        };
+
+        let mut file = match &self.path {
+            Some(path) => LockedFile::open(path)?,
+            None => {
+                if !self.is_repeated(command) {
+                    self.entries.push(entry);
+                }
+                return Ok(());
+            }
+        };
+
+        self.merge(&mut file)?;
+        if self.is_repeated(command) {
+            return Ok(());
+        }
+
+        let line = format_line(&entry);
+        file.file.write_all(line.as_bytes())?;
+        self.read_len += line.len() as u64;
+        self.entries.push(entry);
+        Ok(())
+    }
+
+    fn is_repeated(&self, command: &str) -> bool {
+        self.entries.last().is_some_and(|last| last.command == command)
+    }
+
+    // removes the entries for which `remove` returns true, returns how many were removed
+    pub fn remove<F>(&mut self, mut remove: F) -> io::Result<usize>
+        where F: FnMut(usize, &HistoryEntry) -> bool
+    {
+        let mut file = match &self.path {
+            Some(path) => Some(LockedFile::open(path)?),
+            None => None
+        };
+
+        if let Some(file) = &mut file {
+            self.merge(file)?;
+        }
+
+        let before = self.entries.len();
+        let mut index = 0;
+        self.entries.retain(|entry| {
+            index += 1;
+            !remove(index - 1, entry)
+        });
+        let removed = before - self.entries.len();
+
+        // rewritten in place, as the other instances hold the file open for appending
+        if let Some(file) = &mut file {
+            let contents: String = self.entries.iter().map(format_line).collect();
+            file.file.set_len(0)?;
+            file.file.write_all(contents.as_bytes())?;
+            self.read_len = contents.len() as u64;
+        }
+
+        Ok(removed)
+    }
+}
+
+fn format_line(entry: &HistoryEntry) -> String {
+    format!("{}\t{}\t{}\n", entry.timestamp, escape(&entry.command), escape(&entry.cwd))
+}
+
+// local time like `2024-01-31 13:37:00`
+pub fn format_time(timestamp: u64) -> String {
+    let time = timestamp as libc::time_t;
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    let mut buf = [0u8; 32];
+
+    let len = unsafe {
+        libc::localtime_r(&time, &mut tm);
+        libc::strftime(buf.as_mut_ptr() as *mut libc::c_char, buf.len(), b"%Y-%m-%d %H:%M:%S\0".as_ptr() as *const libc::c_char, &tm)
+    };
+    String::from_utf8_lossy(&buf[..len]).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    // a history file in a directory of its own
+    fn history_path(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("sheesh-history-{}-{}", std::process::id(), name));
+        fs::remove_dir_all(&dir).ok();
+        dir.join(FILE_NAME)
+    }
+
+    fn open(path: &Path) -> History {
+        let mut history = History::new();
+        history.path = Some(path.to_path_buf());
+        history
+    }
+
+    fn commands(history: &History) -> Vec<&str> {
+        history.entries().iter().map(|entry| entry.command.as_str()).collect()
+    }
+
+    #[test]
+    fn add_merges_lines_of_other_instances() {
+        let path = history_path("merge");
+        let (mut first, mut second) = (open(&path), open(&path));
+
+        first.add("one", String::from("/")).unwrap();
+        second.add("if true\n\techo two\nend", String::from("/tmp")).unwrap();
+        first.add("three", String::from("/")).unwrap();
+
+        assert_eq!(commands(&first), ["one", "if true\n\techo two\nend", "three"]);
+        assert_eq!(first.entries()[1].cwd, "/tmp");
+
+        // a line still being written is left for later
+        let mut file = LockedFile::open(&path).unwrap();
+        file.file.write_all(b"4\tfour").unwrap();
+        second.merge(&mut file).unwrap();
+        assert_eq!(commands(&second), ["one", "if true\n\techo two\nend", "three"]);
+        file.file.write_all(b"\t/\n").unwrap();
+        second.merge(&mut file).unwrap();
+        assert_eq!(commands(&second), ["one", "if true\n\techo two\nend", "three", "four"]);
+
+        fs::remove_dir_all(path.parent().unwrap()).ok();
+    }
+
+    #[test]
+    fn add_skips_repeated_commands() {
+        let path = history_path("dedup");
+        let (mut first, mut second) = (open(&path), open(&path));
+
+        first.add("ls", String::from("/")).unwrap();
+        first.add("ls", String::from("/")).unwrap();
+        second.add("ls", String::from("/")).unwrap();
+        second.add("pwd", String::from("/")).unwrap();
+        second.add("ls", String::from("/")).unwrap();
+
+        assert_eq!(commands(&second), ["ls", "pwd", "ls"]);
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 3);
+
+        fs::remove_dir_all(path.parent().unwrap()).ok();
+    }
+
+    #[test]
+    fn remove_rewrites_the_file_for_all_instances() {
+        let path = history_path("rewrite");
+        let (mut first, mut second) = (open(&path), open(&path));
+
+        for command in ["one", "two", "three"] {
+            first.add(command, String::from("/")).unwrap();
+        }
+        assert_eq!(second.remove(|_, entry| entry.command == "two").unwrap(), 1);
+        assert_eq!(commands(&second), ["one", "three"]);
+
+        // the shorter file makes the other instance read it again
+        first.add("four", String::from("/")).unwrap();
+        assert_eq!(commands(&first), ["one", "three", "four"]);
+
+        fs::remove_dir_all(path.parent().unwrap()).ok();
+    }
+}
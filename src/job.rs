@@ -0,0 +1,550 @@
+use std::{
+    fmt,
+    io,
+    mem,
+    sync::atomic::{
+        AtomicU64,
+        Ordering
+    },
+    time::{
+        Duration,
+        Instant
+    }
+};
+
+use libc::{
+    pid_t,
+    termios
+};
+
+use crate::signal;
+
+// CPU time used by all reaped children, in microseconds
+static CHILD_USER_TIME: AtomicU64 = AtomicU64::new(0);
+static CHILD_SYSTEM_TIME: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum JobState {
+    Running,
+    Stopped,
+    Done(i32)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WaitStatus {
+    Exited(i32),
+    Signaled(i32),
+    Stopped(i32),
+    Continued
+}
+
+impl WaitStatus {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Self::Exited(code) => *code,
+            Self::Signaled(sig) => 128 + sig,
+            Self::Stopped(sig) => 128 + sig,
+            Self::Continued => 0
+        }
+    }
+}
+
+pub struct Job {
+    id: usize,
+    pid: u32, // also the process group id with job control
+    command: String,
+    state: JobState,
+    tmodes: Option<termios> // terminal modes of a stopped job
+}
+
+impl Job {
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    pub fn command(&self) -> &String {
+        &self.command
+    }
+}
+
+impl fmt::Display for Job {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let state = match self.state {
+            JobState::Running => String::from("Running"),
+            JobState::Stopped => String::from("Stopped"),
+            JobState::Done(0) => String::from("Done"),
+            JobState::Done(code) => format!("Exit {}", code)
+        };
+
+        write!(f, "[{}]  {:<24}{}", self.id, state, self.command)
+    }
+}
+
+// all processes started in the background with `&` or stopped with Ctrl-Z
+pub struct JobTable {
+    jobs: Vec<Job>,
+    shell_pgid: Option<pid_t>, // only set if job control is enabled
+    shell_tmodes: Option<termios>
+}
+
+impl JobTable {
+    pub fn new() -> Self {
+        Self {
+            jobs: Vec::new(),
+            shell_pgid: None,
+            shell_tmodes: None
+        }
+    }
+
+    // puts the shell into its own process group in the foreground of the terminal,
+    // so that every command can get a process group of its own
+    pub fn init_job_control(&mut self) {
+        unsafe {
+            if libc::isatty(libc::STDIN_FILENO) == 0 {
+                return;
+            }
+
+            // wait until the shell is in the foreground
+            loop {
+                let pgid = libc::getpgrp();
+                if libc::tcgetpgrp(libc::STDIN_FILENO) == pgid {
+                    break;
+                }
+                libc::kill(-pgid, libc::SIGTTIN);
+            }
+
+            // Ctrl-Z at the prompt must not stop the shell itself
+            signal::install_noop_handler(libc::SIGTSTP);
+            signal::install_noop_handler(libc::SIGTTIN);
+
+            let pid = libc::getpid();
+            if libc::getpgrp() != pid {
+                libc::setpgid(pid, pid);
+            }
+            set_terminal_pgid(pid);
+
+            let mut tmodes: termios = mem::zeroed();
+            if libc::tcgetattr(libc::STDIN_FILENO, &mut tmodes) == 0 {
+                self.shell_tmodes = Some(tmodes);
+            }
+
+            self.shell_pgid = Some(pid);
+        }
+    }
+
+    pub fn has_job_control(&self) -> bool {
+        self.shell_pgid.is_some()
+    }
+
+    pub fn add(&mut self, pid: u32, command: String) -> usize {
+        self.add_with_state(pid, command, JobState::Running)
+    }
+
+    fn add_with_state(&mut self, pid: u32, command: String, state: JobState) -> usize {
+        let id = self.jobs.iter().map(|job| job.id).max().unwrap_or(0) + 1;
+        self.jobs.push(Job { id, pid, command, state, tmodes: None });
+        id
+    }
+
+    pub fn jobs(&self) -> &Vec<Job> {
+        &self.jobs
+    }
+
+    pub fn get(&self, id: usize) -> Option<&Job> {
+        self.jobs.iter().find(|job| job.id == id)
+    }
+
+    fn get_mut(&mut self, id: usize) -> Option<&mut Job> {
+        self.jobs.iter_mut().find(|job| job.id == id)
+    }
+
+    pub fn remove(&mut self, id: usize) -> Option<Job> {
+        let index = self.jobs.iter().position(|job| job.id == id)?;
+        Some(self.jobs.remove(index))
+    }
+
+    // the most recently started job, referred to by `%%` or `%+`
+    pub fn current(&self) -> Option<&Job> {
+        self.jobs.last()
+    }
+
+    // resolves `%N`, `%%` and `%+` job specs to a job id
+    pub fn parse_spec(&self, spec: &str) -> Option<usize> {
+        match spec {
+            "%%" | "%+" => self.current().map(|job| job.id),
+            _ => spec.strip_prefix('%')?.parse().ok().filter(|id| self.get(*id).is_some())
+        }
+    }
+
+    pub fn find_pid(&self, pid: u32) -> Option<usize> {
+        self.jobs.iter().find(|job| job.pid == pid).map(|job| job.id)
+    }
+
+    // checks all jobs without blocking and removes the finished ones
+    pub fn reap(&mut self) -> Vec<Job> {
+        self.poll();
+
+        let (finished, jobs) = self.jobs.drain(..).partition(|job| matches!(job.state, JobState::Done(_)));
+        self.jobs = jobs;
+        finished
+    }
+
+    // updates the state of all jobs without blocking
+    fn poll(&mut self) {
+        for job in self.jobs.iter_mut().filter(|job| !matches!(job.state, JobState::Done(_))) {
+            match wait_pid(job.pid, false) {
+                Some(WaitStatus::Stopped(_)) => job.state = JobState::Stopped,
+                Some(WaitStatus::Continued) => job.state = JobState::Running,
+                Some(status) => job.state = JobState::Done(status.exit_code()),
+                None => ()
+            }
+        }
+    }
+
+    // blocks until the given job has finished or was stopped and returns its exit code
+    pub fn wait(&mut self, id: usize) -> Option<i32> {
+        let job = self.get(id)?;
+        let status = match job.state {
+            JobState::Done(code) => WaitStatus::Exited(code),
+            // a stopped job would never finish on its own
+            JobState::Stopped => return Some(WaitStatus::Stopped(libc::SIGTSTP).exit_code()),
+            JobState::Running => wait_pid(job.pid, true).unwrap_or(WaitStatus::Exited(0))
+        };
+
+        if let WaitStatus::Stopped(_) = status {
+            self.get_mut(id).unwrap().state = JobState::Stopped;
+        }
+        else {
+            self.remove(id);
+        }
+
+        Some(status.exit_code())
+    }
+
+    // blocks until any of the given jobs has finished, returns its id and exit code
+    pub fn wait_any(&mut self, ids: &[usize]) -> Option<(usize, i32)> {
+        loop {
+            self.poll();
+
+            let finished = self.jobs.iter()
+                .filter(|job| ids.contains(&job.id))
+                .find_map(|job| match job.state {
+                    JobState::Done(code) => Some((job.id, code)),
+                    _ => None
+                });
+
+            if let Some((id, code)) = finished {
+                self.remove(id);
+                return Some((id, code));
+            }
+
+            if !self.jobs.iter().any(|job| ids.contains(&job.id) && job.state == JobState::Running) {
+                return None;
+            }
+
+            // sleep until any child changes its state, leaving it to `poll` to reap
+            let mut info: libc::siginfo_t = unsafe { mem::zeroed() };
+            let options = libc::WEXITED | libc::WSTOPPED | libc::WNOWAIT;
+            if unsafe { libc::waitid(libc::P_ALL, 0, &mut info, options) } < 0 {
+                if io::Error::last_os_error().raw_os_error() != Some(libc::EINTR) {
+                    return None;
+                }
+                continue;
+            }
+
+            // a child outside the job table would wake this up over and over again
+            let pid = unsafe { info.si_pid() } as u32;
+            if !self.jobs.iter().any(|job| job.pid == pid) {
+                wait_pid(pid, false);
+            }
+        }
+    }
+
+    // runs a freshly spawned process in the foreground until it exits or gets stopped
+    pub fn foreground(&mut self, pid: u32, command: String) -> i32 {
+        let status = self.wait_foreground(pid, None, false, || wait_pid(pid, true).unwrap_or(WaitStatus::Exited(0)));
+        self.report_foreground(pid, command, status)
+    }
+
+    // like `foreground`, but sends `sig` to the process group once the time limit has passed
+    // and SIGKILL after another `kill_after`, if given; returns 124 if it timed out
+    pub fn foreground_timeout(
+        &mut self, pid: u32, command: String, limit: Duration, sig: libc::c_int, kill_after: Option<Duration>
+    ) -> i32 {
+        let mut timed_out = false;
+        let mut killed = false;
+
+        let status = self.wait_foreground(pid, None, false, || {
+            let mut deadline = Instant::now() + limit;
+            loop {
+                if let Some(status) = wait_pid_until(pid, deadline) {
+                    return status;
+                }
+
+                if timed_out {
+                    killed = true;
+                    signal_job(pid, libc::SIGKILL, true).ok();
+                    return wait_pid(pid, true).unwrap_or(WaitStatus::Exited(0));
+                }
+
+                timed_out = true;
+                signal_job(pid, sig, true).ok();
+                signal_job(pid, libc::SIGCONT, true).ok();
+                match kill_after {
+                    Some(kill_after) => deadline = Instant::now() + kill_after,
+                    None => return wait_pid(pid, true).unwrap_or(WaitStatus::Exited(0))
+                }
+            }
+        });
+
+        // the forked shell may be gone while other processes of its group ignore the signal
+        if timed_out && !killed {
+            if let Some(kill_after) = kill_after {
+                let deadline = Instant::now() + kill_after;
+                while signal_job(pid, 0, true).is_ok() && Instant::now() < deadline {
+                    std::thread::sleep(Duration::from_millis(10));
+                }
+                killed = signal_job(pid, libc::SIGKILL, true).is_ok();
+            }
+        }
+
+        if killed {
+            return WaitStatus::Signaled(libc::SIGKILL).exit_code();
+        }
+        if timed_out && !matches!(status, WaitStatus::Stopped(_)) {
+            return 124;
+        }
+        self.report_foreground(pid, command, status)
+    }
+
+    fn report_foreground(&mut self, pid: u32, command: String, status: WaitStatus) -> i32 {
+        match status {
+            WaitStatus::Stopped(_) => {
+                let id = self.add_with_state(pid, command, JobState::Stopped);
+                self.save_job_tmodes(id);
+                eprintln!("\n{}", self.get(id).unwrap());
+            }
+            WaitStatus::Signaled(libc::SIGINT) => {
+                // abort the rest of the input, like Ctrl-C reaching the shell would
+                signal::interrupt();
+                eprintln!();
+            }
+            WaitStatus::Signaled(libc::SIGPIPE) => (),
+            WaitStatus::Signaled(sig) => eprintln!("sheesh: {} in `{}`", signal::signal_name(sig), command),
+            _ => ()
+        }
+
+        status.exit_code()
+    }
+
+    // continues a job in the foreground (`fg`)
+    pub fn continue_foreground(&mut self, id: usize) -> Option<i32> {
+        let job = self.get_mut(id)?;
+        job.state = JobState::Running;
+        let (pid, tmodes) = (job.pid, job.tmodes.take());
+
+        let status = self.wait_foreground(pid, tmodes, true, || wait_pid(pid, true).unwrap_or(WaitStatus::Exited(0)));
+        match status {
+            WaitStatus::Stopped(_) => {
+                self.get_mut(id).unwrap().state = JobState::Stopped;
+                self.save_job_tmodes(id);
+                eprintln!("\n{}", self.get(id).unwrap());
+            }
+            _ => {
+                self.remove(id);
+            }
+        }
+
+        Some(status.exit_code())
+    }
+
+    // sends a signal to every process of a job
+    pub fn signal(&mut self, id: usize, sig: libc::c_int) -> io::Result<()> {
+        let job_control = self.has_job_control();
+        let job = self.get(id).ok_or_else(|| io::Error::from_raw_os_error(libc::ESRCH))?;
+        signal_job(job.pid, sig, job_control)?;
+
+        // a stopped job would only act on the signal once it gets continued
+        if job.state == JobState::Stopped && (sig == libc::SIGTERM || sig == libc::SIGHUP) {
+            signal_job(job.pid, libc::SIGCONT, job_control)?;
+        }
+        Ok(())
+    }
+
+    // continues a stopped job in the background (`bg`)
+    pub fn continue_background(&mut self, id: usize) -> Option<&Job> {
+        let job_control = self.has_job_control();
+        let job = self.get_mut(id)?;
+
+        signal_job(job.pid, libc::SIGCONT, job_control).ok();
+        job.state = JobState::Running;
+        Some(job)
+    }
+
+    fn wait_foreground<F>(&mut self, pid: u32, tmodes: Option<termios>, resume: bool, wait: F) -> WaitStatus
+        where F: FnOnce() -> WaitStatus
+    {
+        let shell_pgid = match self.shell_pgid {
+            Some(shell_pgid) => shell_pgid,
+            None => {
+                if resume {
+                    signal_job(pid, libc::SIGCONT, false).ok();
+                }
+
+                // the child shares our process group, so Ctrl-C has to be forwarded
+                signal::set_foreground_pid(Some(pid));
+                let status = wait();
+                signal::set_foreground_pid(None);
+                return status;
+            }
+        };
+
+        set_terminal_pgid(pid as pid_t);
+        if let Some(tmodes) = tmodes {
+            unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSADRAIN, &tmodes) };
+        }
+        if resume {
+            signal_job(pid, libc::SIGCONT, true).ok();
+        }
+
+        let status = wait();
+
+        // take the terminal back
+        set_terminal_pgid(shell_pgid);
+        if let Some(shell_tmodes) = &self.shell_tmodes {
+            unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSADRAIN, shell_tmodes) };
+        }
+        status
+    }
+
+    fn save_job_tmodes(&mut self, id: usize) {
+        if self.shell_pgid.is_none() {
+            return;
+        }
+
+        let mut tmodes: termios = unsafe { mem::zeroed() };
+        if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut tmodes) } == 0 {
+            self.get_mut(id).unwrap().tmodes = Some(tmodes);
+        }
+    }
+}
+
+// hands the terminal to a process group; SIGTTOU is blocked meanwhile, since the shell
+// might call this while it is not in the foreground itself
+fn set_terminal_pgid(pgid: pid_t) {
+    unsafe {
+        let mut mask: libc::sigset_t = mem::zeroed();
+        let mut old_mask: libc::sigset_t = mem::zeroed();
+        libc::sigemptyset(&mut mask);
+        libc::sigaddset(&mut mask, libc::SIGTTOU);
+
+        libc::sigprocmask(libc::SIG_BLOCK, &mask, &mut old_mask);
+        libc::tcsetpgrp(libc::STDIN_FILENO, pgid);
+        libc::sigprocmask(libc::SIG_SETMASK, &old_mask, std::ptr::null_mut());
+    }
+}
+
+// puts a freshly spawned child into its own process group; done by the parent as well
+// as the child to avoid racing against the exec
+pub fn set_process_group(pid: u32) {
+    unsafe { libc::setpgid(pid as pid_t, pid as pid_t) };
+}
+
+// sends a signal to a job, to its whole process group if job control is enabled
+pub fn signal_job(pid: u32, sig: libc::c_int, group: bool) -> io::Result<()> {
+    signal_pid(if group { -(pid as pid_t) } else { pid as pid_t }, sig)
+}
+
+// negative pids address a whole process group, like with kill(2)
+pub fn signal_pid(pid: pid_t, sig: libc::c_int) -> io::Result<()> {
+    if unsafe { libc::kill(pid, sig) } < 0 {
+        Err(io::Error::last_os_error())
+    }
+    else {
+        Ok(())
+    }
+}
+
+// returns the new status of a child process, or `None` if it is unchanged
+pub fn wait_pid(pid: u32, block: bool) -> Option<WaitStatus> {
+    let mut status = 0;
+    let mut options = libc::WUNTRACED | libc::WCONTINUED;
+    if !block {
+        options |= libc::WNOHANG;
+    }
+
+    loop {
+        let mut usage: libc::rusage = unsafe { mem::zeroed() };
+        let result = unsafe { libc::wait4(pid as pid_t, &mut status, options, &mut usage) };
+        if result == 0 {
+            return None;
+        }
+        else if result < 0 {
+            // interrupted by a signal, try again
+            if io::Error::last_os_error().raw_os_error() == Some(libc::EINTR) {
+                continue;
+            }
+            // the child was already reaped elsewhere, report it like an unknown job
+            return Some(WaitStatus::Exited(127));
+        }
+
+        if libc::WIFEXITED(status) || libc::WIFSIGNALED(status) {
+            CHILD_USER_TIME.fetch_add(timeval_micros(&usage.ru_utime), Ordering::SeqCst);
+            CHILD_SYSTEM_TIME.fetch_add(timeval_micros(&usage.ru_stime), Ordering::SeqCst);
+        }
+
+        if libc::WIFEXITED(status) {
+            return Some(WaitStatus::Exited(libc::WEXITSTATUS(status)));
+        }
+        else if libc::WIFSIGNALED(status) {
+            return Some(WaitStatus::Signaled(libc::WTERMSIG(status)));
+        }
+        else if libc::WIFSTOPPED(status) {
+            return Some(WaitStatus::Stopped(libc::WSTOPSIG(status)));
+        }
+        else if libc::WIFCONTINUED(status) {
+            if block {
+                continue;
+            }
+            return Some(WaitStatus::Continued);
+        }
+    }
+}
+
+// polls a child process until its status changes or the deadline has passed
+fn wait_pid_until(pid: u32, deadline: Instant) -> Option<WaitStatus> {
+    loop {
+        match wait_pid(pid, false) {
+            Some(WaitStatus::Continued) | None => (),
+            status => return status
+        }
+
+        let now = Instant::now();
+        if now >= deadline {
+            return None;
+        }
+        std::thread::sleep((deadline - now).min(Duration::from_millis(10)));
+    }
+}
+
+fn timeval_micros(time: &libc::timeval) -> u64 {
+    time.tv_sec as u64 * 1_000_000 + time.tv_usec as u64
+}
+
+// user and system CPU time of the shell itself
+pub fn shell_times() -> (Duration, Duration) {
+    let mut usage: libc::rusage = unsafe { mem::zeroed() };
+    unsafe { libc::getrusage(libc::RUSAGE_SELF, &mut usage) };
+    (
+        Duration::from_micros(timeval_micros(&usage.ru_utime)),
+        Duration::from_micros(timeval_micros(&usage.ru_stime))
+    )
+}
+
+// user and system CPU time of all children reaped so far
+pub fn child_times() -> (Duration, Duration) {
+    (
+        Duration::from_micros(CHILD_USER_TIME.load(Ordering::SeqCst)),
+        Duration::from_micros(CHILD_SYSTEM_TIME.load(Ordering::SeqCst))
+    )
+}
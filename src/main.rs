@@ -2,9 +2,10 @@ use std::{
     process, 
     env,
     io::{
-        self, 
-        Write
-    }
+        self,
+        Read
+    },
+    time::Instant
 };
 
 mod shell;
@@ -13,54 +14,333 @@ mod command;
 mod evaluate;
 mod environment;
 mod builtin;
+mod universal;
+mod command_cache;
+mod job;
+mod signal;
+mod resource;
+mod redirect;
+mod editor;
+mod history;
+mod complete;
+mod generate;
+mod highlight;
+mod prompt;
 
 use shell::Error;
 use environment::Environment;
+use editor::{
+    Editor,
+    Input
+};
+
+const USAGE: &str = "\
+usage: sheesh [options] [script [args...]]
+       sheesh [options] -c command [args...]
+       sheesh [options] -s [args...]
+
+options:
+  -c, --command COMMAND   run COMMAND instead of a script
+  -s, --stdin             read the script from standard input
+  -n, --no-execute        only check the syntax
+  -i, --interactive       start the interactive shell
+  -l, --login             run as a login shell, also loading the profile files
+      --generate-completions [COMMAND...]
+                          generate completions from man pages and --help output
+  -h, --help              show this help
+      --version           show the version";
+
+// where the commands come from
+#[derive(Debug, PartialEq)]
+enum Source {
+    Repl,
+    Command(String),
+    Stdin,
+    Script(String)
+}
+
+// what to do instead of running commands
+#[derive(Debug, PartialEq)]
+enum Action {
+    Run,
+    Help,
+    Version,
+    GenerateCompletions // for the commands in `args`
+}
+
+struct Options {
+    action: Action,
+    source: Source,
+    args: Vec<String>, // `$argv` of the script or command
+    no_execute: bool,
+    login: bool
+}
 
 fn main() {
     let mut args: Vec<String> = env::args().collect();
-    args.remove(0);
+    let name = args.remove(0);
 
-    let mut env = Environment::new(env::vars());
+    let options = match parse_options(args) {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("sheesh: {}\nTry `sheesh --help` for more information.", err);
+            process::exit(2)
+        }
+    };
 
-    if args.len() == 0 {
-        repl(&mut env);
+    match options.action {
+        Action::Run => (),
+        Action::Help => {
+            println!("{}", USAGE);
+            process::exit(0)
+        }
+        Action::Version => {
+            println!("sheesh {}", env!("CARGO_PKG_VERSION"));
+            process::exit(0)
+        }
+        Action::GenerateCompletions => process::exit(generate::generate_completions(&options.args))
     }
-    else {
-        let mut last_exit_code = 0;
-        for arg in args {
-            let res = shell::run_script(arg, &mut env);
-            if let Err(err) = res {
-                if let Error::EarlyExit(exit_code) = err {
-                    process::exit(exit_code)
-                }
+
+    // set for the copies of the shell running timed builtins and functions, which get
+    // their state from the script instead
+    let no_config = env::var_os(shell::NO_CONFIG_VAR).is_some();
+    env::remove_var(shell::NO_CONFIG_VAR);
+
+    let mut env = Environment::new(env::vars());
+
+    // a login shell is started as `-sheesh`, or with `-l`
+    env.set_login(options.login || name.starts_with('-'));
+    env.set_interactive(matches!(options.source, Source::Repl));
+
+    let script_name = match &options.source {
+        Source::Script(path) => path.clone(),
+        _ => name
+    };
+    set_arguments(&script_name, &options.args, &mut env);
+
+    if options.no_execute {
+        let input = match options.source {
+            Source::Command(command) => Ok(command),
+            Source::Script(path) => shell::read_file(path),
+            Source::Repl | Source::Stdin => read_stdin()
+        };
+
+        match input.and_then(shell::check_syntax) {
+            Ok(_) => process::exit(0),
+            Err(err) => {
                 eprintln!("{}", err);
+                process::exit(2)
             }
-            else {
-                last_exit_code = res.unwrap();
+        }
+    }
+
+    // the interactive shell loads it once the terminal is set up
+    if !no_config && !matches!(options.source, Source::Repl) {
+        load_config(&mut env);
+    }
+
+    let input = match options.source {
+        Source::Repl => repl(&mut env, !no_config),
+        Source::Command(command) => Ok(command),
+        Source::Script(path) => shell::read_file(path),
+        Source::Stdin => read_stdin()
+    };
+
+    let exit_code = match input.and_then(|input| shell::execute(input, &mut env)) {
+        Ok(exit_code) => exit_code,
+        Err(Error::EarlyExit(exit_code)) => exit_code,
+        Err(err) => {
+            eprintln!("{}", err);
+            if let Error::CommandNotFound(_) = err { 127 } else { 1 }
+        }
+    };
+    exit(&mut env, exit_code)
+}
+
+fn parse_options(args: Vec<String>) -> Result<Options, String> {
+    let mut options = Options {
+        action: Action::Run,
+        source: Source::Repl,
+        args: Vec::new(),
+        no_execute: false,
+        login: false
+    };
+    let (mut command, mut stdin, mut interactive) = (None, false, false);
+
+    // combined short flags like `-lc` are split up first
+    let mut flags = Vec::new();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            break;
+        }
+        if !arg.starts_with('-') || arg.len() < 2 {
+            options.args.push(arg);
+            break;
+        }
+
+        let group = if arg.starts_with("--") {
+            vec![arg]
+        } else {
+            arg.chars().skip(1).map(|flag| format!("-{}", flag)).collect()
+        };
+
+        // the command of `-c` is not a flag, even if it starts with `-`
+        for flag in group {
+            let takes_command = flag == "-c" || flag == "--command";
+            flags.push(flag);
+            if takes_command {
+                if let Some(command) = args.next() {
+                    flags.push(command);
+                }
             }
         }
-        process::exit(last_exit_code);
+    }
+    options.args.extend(args);
+
+    let mut flags = flags.into_iter();
+    while let Some(flag) = flags.next() {
+        match flag.as_str() {
+            "-c" | "--command" => match flags.next() {
+                Some(next) => command = Some(next),
+                None => return Err(String::from("`-c` expects a command"))
+            },
+            "-s" | "--stdin" => stdin = true,
+            "-n" | "--no-execute" => options.no_execute = true,
+            "-i" | "--interactive" => interactive = true,
+            "-l" | "--login" => options.login = true,
+            "-h" | "--help" => options.action = Action::Help,
+            "--version" => options.action = Action::Version,
+            "--generate-completions" => options.action = Action::GenerateCompletions,
+            _ => return Err(format!("unknown option `{}`", flag))
+        }
+    }
+
+    if options.action != Action::Run {
+        return Ok(options);
+    }
+
+    // the interactive shell reads its commands from the terminal only
+    if interactive && (command.is_some() || stdin || !options.args.is_empty()) {
+        return Err(String::from("`-i` cannot be combined with `-c`, `-s` or a script"));
+    }
+
+    options.source = if let Some(command) = command {
+        Source::Command(command)
+    }
+    else if stdin {
+        Source::Stdin
+    }
+    else if !options.args.is_empty() {
+        Source::Script(options.args.remove(0))
+    }
+    // input that is no terminal is a script, like with `curl ... | sheesh`
+    else if interactive || unsafe { libc::isatty(libc::STDIN_FILENO) } != 0 {
+        Source::Repl
+    }
+    else {
+        Source::Stdin
+    };
+
+    Ok(options)
+}
+
+// `$0`, and the arguments as `$argv` and `$1`, `$2`...
+fn set_arguments(name: &str, args: &[String], env: &mut Environment) {
+    let mut vars = vec![(String::from("0"), name.to_string()), (String::from("argv"), args.join(" "))];
+    vars.extend(args.iter().enumerate().map(|(i, arg)| ((i + 1).to_string(), arg.clone())));
+
+    for (name, value) in vars {
+        if let Err(err) = env.add_var(name, value) {
+            eprintln!("sheesh: {}", err);
+        }
+    }
+}
+
+fn read_stdin() -> Result<String, Error> {
+    let mut input = String::new();
+    match io::stdin().read_to_string(&mut input) {
+        Ok(_) => Ok(input),
+        Err(err) => Err(Error::ReadFile(String::from("<stdin>"), err.to_string()))
     }
 }
 
-fn repl(mut env: &mut Environment) {
+fn load_config(env: &mut Environment) {
+    if let Err(Error::EarlyExit(exit_code)) = shell::load_config(env) {
+        exit(env, exit_code)
+    }
+}
+
+// runs the `EXIT` trap before leaving the shell
+fn exit(env: &mut Environment, exit_code: i32) -> ! {
+    match shell::run_trap("EXIT", env) {
+        Err(Error::EarlyExit(code)) => process::exit(code),
+        _ => process::exit(exit_code)
+    }
+}
+
+fn repl(env: &mut Environment, with_config: bool) -> ! {
     let mut last_exit_code = 0;
+    let mut editor = Editor::new();
+
+    signal::init_interactive();
+    env.jobs_mut().init_job_control();
+    env.history_mut().load();
+
+    if with_config {
+        load_config(env);
+    }
 
     loop {
-        // print the prompt
-        print!("({}) >>> ", last_exit_code);
-        io::stdout().flush().expect("error while flushing stdout");
+        // run traps for signals received while waiting for input
+        if let Err(Error::EarlyExit(exit_code)) = shell::run_pending_traps(env) {
+            exit(env, exit_code)
+        }
+
+        // pick up universal variables changed by other instances
+        env.sync_universal();
+
+        // report background jobs that finished since the last prompt
+        for job in env.jobs_mut().reap() {
+            eprintln!("{}", job);
+        }
 
-        // get the user input
-        let mut input: String = String::new();
-        io::stdin().read_line(&mut input).unwrap();
+        // get the user input, with more lines while a block or string is open
+        let prompt = prompt::render(last_exit_code, env);
+        let input = match editor.read_line(&prompt, env) {
+            Ok(Input::Line(input)) => input,
+            // Ctrl-C pressed at the prompt cancels all of the input
+            Ok(Input::Interrupted) => String::new(),
+            Ok(Input::Eof) => exit(env, last_exit_code),
+            Err(err) => {
+                eprintln!("sheesh: cannot read input: {}", err);
+                exit(env, 1)
+            }
+        };
+
+        // Ctrl-C pressed at the prompt must not interrupt the next command
+        signal::take_interrupted();
+        if input.trim().is_empty() {
+            continue;
+        }
+
+        let cwd = env::current_dir().map(|cwd| cwd.to_string_lossy().to_string()).unwrap_or_default();
+        if let Err(err) = env.history_mut().add(input.trim_end_matches('\n'), cwd) {
+            eprintln!("sheesh: cannot save history: {}", err);
+        }
 
         // execute the code
-        match shell::execute(input, &mut env) {
+        let start = Instant::now();
+        let result = shell::execute(input, env);
+        env.set_internal_var("CMD_DURATION", start.elapsed().as_millis().to_string());
+
+        match result {
+            Err(Error::Interrupted) => {
+                last_exit_code = 130;
+            }
             Err(err) => {
                 if let Error::EarlyExit(exit_code) = err {
-                    process::exit(exit_code)
+                    exit(env, exit_code)
                 }
                 eprintln!("{}", err);
             }
@@ -69,4 +349,62 @@ fn repl(mut env: &mut Environment) {
             }
         }        
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Options, String> {
+        parse_options(args.iter().map(|arg| arg.to_string()).collect())
+    }
+
+    #[test]
+    fn command_and_its_arguments() {
+        let options = parse(&["-lc", "echo $1", "a", "-b"]).unwrap();
+        assert_eq!(options.source, Source::Command(String::from("echo $1")));
+        assert_eq!(options.args, ["a", "-b"]);
+        assert!(options.login);
+
+        // the command is the next argument, even after a later flag of the group
+        let options = parse(&["-cn", "-x"]).unwrap();
+        assert_eq!(options.source, Source::Command(String::from("-x")));
+        assert!(options.no_execute);
+
+        assert_eq!(parse(&["-c"]).err().unwrap(), "`-c` expects a command");
+        assert_eq!(parse(&["--login", "--command"]).err().unwrap(), "`-c` expects a command");
+    }
+
+    #[test]
+    fn script_stdin_and_the_end_of_options() {
+        let options = parse(&["-n", "script.sh", "-c", "x"]).unwrap();
+        assert_eq!(options.source, Source::Script(String::from("script.sh")));
+        assert_eq!(options.args, ["-c", "x"]);
+
+        let options = parse(&["-s", "--", "-a"]).unwrap();
+        assert_eq!(options.source, Source::Stdin);
+        assert_eq!(options.args, ["-a"]);
+
+        let options = parse(&["--", "-script"]).unwrap();
+        assert_eq!(options.source, Source::Script(String::from("-script")));
+    }
+
+    #[test]
+    fn information_options_anywhere() {
+        assert_eq!(parse(&["--help"]).unwrap().action, Action::Help);
+        assert_eq!(parse(&["-lh"]).unwrap().action, Action::Help);
+        assert_eq!(parse(&["-i", "--version"]).unwrap().action, Action::Version);
+
+        let options = parse(&["--generate-completions", "ls", "grep"]).unwrap();
+        assert_eq!(options.action, Action::GenerateCompletions);
+        assert_eq!(options.args, ["ls", "grep"]);
+    }
+
+    #[test]
+    fn invalid_options() {
+        assert_eq!(parse(&["-x"]).err().unwrap(), "unknown option `-x`");
+        assert_eq!(parse(&["--no-such-thing"]).err().unwrap(), "unknown option `--no-such-thing`");
+        assert!(parse(&["-i", "-c", "echo"]).is_err());
+        assert!(parse(&["-i", "script.sh"]).is_err());
+        assert!(parse(&["-is"]).is_err());
+    }
+}
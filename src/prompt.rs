@@ -0,0 +1,167 @@
+use std::{
+    env,
+    ffi::CStr,
+    time::Duration
+};
+
+use crate::{
+    environment::{
+        Environment,
+        Identifier
+    },
+    highlight,
+    shell
+};
+
+const DEFAULT_PROMPT: &str = "(%s) >>> ";
+const DEFAULT_CONTINUATION_PROMPT: &str = "... ";
+
+// the prompts shown while reading a line
+pub struct Prompt {
+    pub left: String,
+    pub right: String,          // shown at the right edge of the first input row
+    pub continuation: String,   // starts the further rows of incomplete input
+    pub transient: Option<String> // replaces `left` once the line has been submitted
+}
+
+// builds the prompts from the `prompt` and `rprompt` functions, or the `PROMPT`,
+// `RPROMPT`, `PROMPT2` and `transient_prompt` variables
+pub fn render(status: i32, env: &mut Environment) -> Prompt {
+    let left = match run_function("prompt", status, env) {
+        Some(output) => output,
+        None => {
+            let template = template("PROMPT", env).unwrap_or_else(|| DEFAULT_PROMPT.to_string());
+            expand(&template, status, env)
+        }
+    };
+
+    let right = match run_function("rprompt", status, env) {
+        Some(output) => output,
+        None => template("RPROMPT", env).map(|template| expand(&template, status, env)).unwrap_or_default()
+    };
+
+    let continuation = template("PROMPT2", env).unwrap_or_else(|| DEFAULT_CONTINUATION_PROMPT.to_string());
+    let continuation = expand(&continuation, status, env);
+
+    let transient = template("transient_prompt", env).map(|template| expand(&template, status, env));
+
+    Prompt { left, right, continuation, transient }
+}
+
+fn template(name: &str, env: &Environment) -> Option<String> {
+    env.find_var(&name.to_string()).map(|var| var.value()).filter(|value| !value.is_empty())
+}
+
+// the output of a user-defined prompt function, without its final newline
+fn run_function(name: &str, status: i32, env: &mut Environment) -> Option<String> {
+    if !matches!(env.find_ident(&name.to_string()), Some(Identifier::Function { .. })) {
+        return None;
+    }
+
+    // the function sees the status of the last command, and must not change it
+    env.set_internal_var("status", status.to_string());
+    let output = shell::capture_output(name, env).map(|(_, output)| output).unwrap_or_default();
+    env.set_internal_var("status", status.to_string());
+
+    Some(output.strip_suffix('\n').unwrap_or(&output).to_string())
+}
+
+// replaces the `%` escapes of a prompt template:
+//   %~  working directory, with `~` for $HOME     %s  status of the last command
+//   %u  user name                                 %d  duration of the last command
+//   %h  host name, up to the first `.`            %j  number of jobs
+//   %F{color}  starts a color, like `color_*`     %f  resets the color
+fn expand(template: &str, status: i32, env: &Environment) -> String {
+    let mut out = String::new();
+    let mut chars = template.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('~') => out += &working_directory(env),
+            Some('u') => out += &user_name(env),
+            Some('h') => out += &host_name(),
+            Some('s') => out += &status.to_string(),
+            Some('d') => out += &command_duration(env),
+            Some('j') => out += &env.jobs().jobs().len().to_string(),
+            Some('f') => out += "\x1b[0m",
+            Some('F') => match chars.as_str().strip_prefix('{').and_then(|rest| rest.split_once('}')) {
+                Some((spec, rest)) => {
+                    out += &highlight::parse_color(spec);
+                    chars = rest.chars();
+                }
+                None => out += "%F"
+            },
+            Some('%') => out.push('%'),
+            Some(c) => {
+                out.push('%');
+                out.push(c);
+            }
+            None => out.push('%')
+        }
+    }
+
+    out
+}
+
+fn working_directory(env: &Environment) -> String {
+    let cwd = env::current_dir().map(|cwd| cwd.to_string_lossy().to_string()).unwrap_or_default();
+    let home = env.find_var(&String::from("HOME")).map(|var| var.value()).unwrap_or_default();
+
+    if !home.is_empty() && home != "/" {
+        if cwd == home {
+            return String::from("~");
+        }
+        if let Some(rest) = cwd.strip_prefix(&format!("{}/", home.trim_end_matches('/'))) {
+            return format!("~/{}", rest);
+        }
+    }
+    cwd
+}
+
+fn user_name(env: &Environment) -> String {
+    if let Some(user) = env.find_var(&String::from("USER")).map(|var| var.value()).filter(|user| !user.is_empty()) {
+        return user;
+    }
+
+    let passwd = unsafe { libc::getpwuid(libc::geteuid()) };
+    if passwd.is_null() {
+        return String::new();
+    }
+    unsafe { CStr::from_ptr((*passwd).pw_name) }.to_string_lossy().to_string()
+}
+
+fn host_name() -> String {
+    let mut buf = [0u8; 256];
+    if unsafe { libc::gethostname(buf.as_mut_ptr() as *mut libc::c_char, buf.len()) } < 0 {
+        return String::new();
+    }
+
+    let len = buf.iter().position(|byte| *byte == 0).unwrap_or(buf.len());
+    let name = String::from_utf8_lossy(&buf[..len]).to_string();
+    name.split('.').next().unwrap_or_default().to_string()
+}
+
+// $CMD_DURATION like `42ms`, `3.5s` or `2m10s`, empty before the first command
+fn command_duration(env: &Environment) -> String {
+    let millis = match env.find_var(&String::from("CMD_DURATION")).and_then(|var| var.value().parse().ok()) {
+        Some(millis) => millis,
+        None => return String::new()
+    };
+    format_duration(Duration::from_millis(millis))
+}
+
+fn format_duration(duration: Duration) -> String {
+    let millis = duration.as_millis();
+    if millis < 1000 {
+        format!("{}ms", millis)
+    } else if millis < 60_000 {
+        format!("{:.1}s", duration.as_secs_f64())
+    } else {
+        format!("{}m{}s", millis / 60_000, millis % 60_000 / 1000)
+    }
+}
@@ -0,0 +1,260 @@
+use std::{
+    ffi::CString,
+    fmt,
+    io::{
+        self,
+        Write
+    }
+};
+
+use crate::{
+    command::Value,
+    environment::Environment,
+    shell::Error,
+    token::{
+        Token,
+        TokenKind
+    }
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RedirectMode {
+    Read,   // <
+    Write,  // >
+    Append  // >>
+}
+
+#[derive(Debug, Clone)]
+pub enum RedirectTarget {
+    File(Value),
+    Fd(i32),
+    Close
+}
+
+#[derive(Debug, Clone)]
+pub struct Redirect {
+    pub fd: i32,
+    pub mode: RedirectMode,
+    pub target: RedirectTarget
+}
+
+impl Redirect {
+    // parses a redirection token, reading the following file name token if needed
+    pub fn parse(tok: &Token, tokens: &mut std::slice::Iter<Token>) -> Result<Self, Error> {
+        let value = tok.value();
+        let digits = value.chars().take_while(|ch| ch.is_ascii_digit()).count();
+
+        let mode = match tok.kind() {
+            TokenKind::APPEND => RedirectMode::Append,
+            TokenKind::OUTSTREAM => RedirectMode::Write,
+            _ => RedirectMode::Read
+        };
+
+        let fd = match value[..digits].parse::<i32>() {
+            Ok(fd) => fd,
+            Err(_) if mode == RedirectMode::Read => 0,
+            Err(_) => 1
+        };
+
+        let target = match value.split_once('&') {
+            Some((_, "-")) => RedirectTarget::Close,
+            Some((_, fd)) => RedirectTarget::Fd(fd.parse().map_err(|_| Error::SyntaxError("Expected a file descriptor after `&`"))?),
+            None => match tokens.next() {
+                Some(tok) if matches!(tok.kind(), TokenKind::GENERIC | TokenKind::STRING | TokenKind::VARIABLE) => {
+                    RedirectTarget::File(Value::parse(tok))
+                }
+                _ => return Err(Error::SyntaxError("Expected a file name after redirection"))
+            }
+        };
+
+        Ok(Self { fd, mode, target })
+    }
+
+    fn resolve(&self, env: &mut Environment) -> Result<Redirection, Error> {
+        Ok(Redirection {
+            fd: self.fd,
+            action: match &self.target {
+                RedirectTarget::File(path) => Action::Open(path.eval(env)?, self.mode),
+                RedirectTarget::Fd(fd) => Action::Dup(*fd),
+                RedirectTarget::Close => Action::Close
+            }
+        })
+    }
+}
+
+impl fmt::Display for Redirect {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let default_fd = if self.mode == RedirectMode::Read { 0 } else { 1 };
+        if self.fd != default_fd {
+            write!(f, "{}", self.fd)?;
+        }
+
+        write!(f, "{}", match self.mode {
+            RedirectMode::Read => "<",
+            RedirectMode::Write => ">",
+            RedirectMode::Append => ">>"
+        })?;
+
+        match &self.target {
+            RedirectTarget::File(path) => write!(f, " {}", path),
+            RedirectTarget::Fd(fd) => write!(f, "&{}", fd),
+            RedirectTarget::Close => write!(f, "&-")
+        }
+    }
+}
+
+enum Action {
+    Open(String, RedirectMode),
+    Dup(i32),
+    Close
+}
+
+struct Redirection {
+    fd: i32,
+    action: Action
+}
+
+// the shell's own file descriptors replaced by a redirection, so they can be restored
+pub struct SavedFds {
+    saved: Vec<(i32, Option<i32>)>
+}
+
+impl SavedFds {
+    pub fn restore(self) {
+        flush_std();
+        for (fd, backup) in self.saved.into_iter().rev() {
+            unsafe {
+                match backup {
+                    Some(backup) => {
+                        libc::dup2(backup, fd);
+                        libc::close(backup);
+                    }
+                    None => {
+                        libc::close(fd);
+                    }
+                }
+            }
+        }
+    }
+
+    // keeps the redirections for the rest of the shell's life (`exec >log`)
+    pub fn keep(self) {
+        flush_std();
+        for (_, backup) in self.saved {
+            if let Some(backup) = backup {
+                unsafe { libc::close(backup) };
+            }
+        }
+    }
+}
+
+fn flush_std() {
+    io::stdout().flush().ok();
+    io::stderr().flush().ok();
+}
+
+// applies the redirections to the shell itself; children started meanwhile inherit them
+pub fn apply(redirects: &[Redirect], env: &mut Environment) -> Result<Result<SavedFds, String>, Error> {
+    let mut redirections = Vec::new();
+    for redirect in redirects {
+        redirections.push(redirect.resolve(env)?);
+    }
+
+    flush_std();
+
+    let mut saved = SavedFds { saved: Vec::new() };
+    for redirection in redirections {
+        // backups are close-on-exec and placed above the fds users typically redirect
+        let backup = unsafe { libc::fcntl(redirection.fd, libc::F_DUPFD_CLOEXEC, 10) };
+        saved.saved.push((redirection.fd, if backup < 0 { None } else { Some(backup) }));
+
+        if let Err(err) = redirection.apply() {
+            saved.restore();
+            return Ok(Err(err));
+        }
+    }
+
+    Ok(Ok(saved))
+}
+
+impl Redirection {
+    fn apply(&self) -> Result<(), String> {
+        let source = match &self.action {
+            Action::Close => {
+                unsafe { libc::close(self.fd) };
+                return Ok(());
+            }
+            Action::Dup(fd) => *fd,
+            Action::Open(path, mode) => {
+                let flags = match mode {
+                    RedirectMode::Read => libc::O_RDONLY,
+                    RedirectMode::Write => libc::O_WRONLY | libc::O_CREAT | libc::O_TRUNC,
+                    RedirectMode::Append => libc::O_WRONLY | libc::O_CREAT | libc::O_APPEND
+                };
+
+                let c_path = CString::new(path.as_str()).map_err(|err| format!("{}: {}", path, err))?;
+                let fd = unsafe { libc::open(c_path.as_ptr(), flags, 0o666) };
+                if fd < 0 {
+                    return Err(format!("{}: {}", path, io::Error::last_os_error()));
+                }
+                fd
+            }
+        };
+
+        if source != self.fd {
+            if unsafe { libc::dup2(source, self.fd) } < 0 {
+                return Err(format!("{}: {}", source, io::Error::last_os_error()));
+            }
+
+            if let Action::Open(..) = self.action {
+                unsafe { libc::close(source) };
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        command,
+        token
+    };
+
+    fn redirects(input: &str) -> Result<Vec<Redirect>, Error> {
+        let mut commands = command::parse(token::tokenize(input.to_string())?)?;
+        Ok(commands.remove(0).redirects)
+    }
+
+    #[test]
+    fn parse_file_descriptors_and_modes() {
+        let parsed = redirects("cmd <in 2>>log 2>&1 3<&- > $out").unwrap();
+        let summary: Vec<(i32, RedirectMode)> = parsed.iter().map(|redirect| (redirect.fd, redirect.mode)).collect();
+        assert_eq!(summary, [
+            (0, RedirectMode::Read),
+            (2, RedirectMode::Append),
+            (2, RedirectMode::Write),
+            (3, RedirectMode::Read),
+            (1, RedirectMode::Write)
+        ]);
+
+        assert!(matches!(&parsed[0].target, RedirectTarget::File(Value::PlainText(_, path)) if path == "in"));
+        assert!(matches!(parsed[2].target, RedirectTarget::Fd(1)));
+        assert!(matches!(parsed[3].target, RedirectTarget::Close));
+        assert!(matches!(&parsed[4].target, RedirectTarget::File(Value::Variable(name)) if name == "out"));
+    }
+
+    #[test]
+    fn display_redirections() {
+        let text: Vec<String> = redirects("cmd <in 2>>log 2>&1 3<&- >'a b'").unwrap().iter().map(Redirect::to_string).collect();
+        assert_eq!(text, ["< in", "2>> log", "2>&1", "3<&-", "> 'a b'"]);
+    }
+
+    #[test]
+    fn missing_file_name_is_an_error() {
+        assert!(matches!(redirects("cmd >"), Err(Error::SyntaxError(_))));
+        assert!(matches!(redirects("cmd 2>> ;"), Err(Error::SyntaxError(_))));
+    }
+}
@@ -0,0 +1,198 @@
+use std::{
+    io,
+    mem
+};
+
+pub struct Limit {
+    pub flag: char,
+    pub resource: i32,
+    pub description: &'static str,
+    pub unit: &'static str,
+    pub factor: u64 // how many bytes/seconds one unit of the user-facing value is
+}
+
+pub const LIMITS: [Limit; 10] = [
+    Limit { flag: 'c', resource: libc::RLIMIT_CORE as i32, description: "core file size", unit: "kbytes", factor: 1024 },
+    Limit { flag: 'd', resource: libc::RLIMIT_DATA as i32, description: "data seg size", unit: "kbytes", factor: 1024 },
+    Limit { flag: 'f', resource: libc::RLIMIT_FSIZE as i32, description: "file size", unit: "kbytes", factor: 1024 },
+    Limit { flag: 'l', resource: libc::RLIMIT_MEMLOCK as i32, description: "max locked memory", unit: "kbytes", factor: 1024 },
+    Limit { flag: 'm', resource: libc::RLIMIT_RSS as i32, description: "max memory size", unit: "kbytes", factor: 1024 },
+    Limit { flag: 'n', resource: libc::RLIMIT_NOFILE as i32, description: "open files", unit: "", factor: 1 },
+    Limit { flag: 's', resource: libc::RLIMIT_STACK as i32, description: "stack size", unit: "kbytes", factor: 1024 },
+    Limit { flag: 't', resource: libc::RLIMIT_CPU as i32, description: "cpu time", unit: "seconds", factor: 1 },
+    Limit { flag: 'u', resource: libc::RLIMIT_NPROC as i32, description: "max user processes", unit: "", factor: 1 },
+    Limit { flag: 'v', resource: libc::RLIMIT_AS as i32, description: "virtual memory", unit: "kbytes", factor: 1024 },
+];
+
+impl Limit {
+    pub fn find(flag: char) -> Option<&'static Limit> {
+        LIMITS.iter().find(|limit| limit.flag == flag)
+    }
+
+    // returns the soft and the hard limit
+    pub fn get(&self) -> io::Result<(libc::rlim_t, libc::rlim_t)> {
+        let mut rlim: libc::rlimit = unsafe { mem::zeroed() };
+        if unsafe { libc::getrlimit(self.resource as _, &mut rlim) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok((rlim.rlim_cur, rlim.rlim_max))
+    }
+
+    pub fn set(&self, soft: libc::rlim_t, hard: libc::rlim_t) -> io::Result<()> {
+        let rlim = libc::rlimit { rlim_cur: soft, rlim_max: hard };
+        if unsafe { libc::setrlimit(self.resource as _, &rlim) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    pub fn format(&self, value: libc::rlim_t) -> String {
+        if value == libc::RLIM_INFINITY {
+            String::from("unlimited")
+        }
+        else {
+            (value / self.factor).to_string()
+        }
+    }
+
+    pub fn parse(&self, value: &str) -> Option<libc::rlim_t> {
+        if value == "unlimited" {
+            Some(libc::RLIM_INFINITY)
+        }
+        else {
+            value.parse::<u64>().ok()?.checked_mul(self.factor)
+        }
+    }
+}
+
+pub fn get_niceness() -> io::Result<i32> {
+    // -1 is a valid niceness, only errno tells an error apart
+    unsafe {
+        *libc::__errno_location() = 0;
+        let niceness = libc::getpriority(libc::PRIO_PROCESS, 0);
+        if niceness == -1 && *libc::__errno_location() != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(niceness)
+    }
+}
+
+pub fn set_niceness(pid: u32, niceness: i32) -> io::Result<()> {
+    if unsafe { libc::setpriority(libc::PRIO_PROCESS, pid as libc::id_t, niceness) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+pub fn get_umask() -> libc::mode_t {
+    // umask(2) can only be read by setting it
+    unsafe {
+        let mask = libc::umask(0);
+        libc::umask(mask);
+        mask
+    }
+}
+
+pub fn set_umask(mask: libc::mode_t) {
+    unsafe { libc::umask(mask & 0o777) };
+}
+
+// formats a umask like `u=rwx,g=rx,o=rx`, listing the permissions it allows
+pub fn format_umask_symbolic(mask: libc::mode_t) -> String {
+    let allowed = !mask & 0o777;
+
+    ["u", "g", "o"].iter().enumerate().map(|(i, who)| {
+        let bits = (allowed >> (6 - i * 3)) & 0o7;
+        let perms: String = [(0o4, 'r'), (0o2, 'w'), (0o1, 'x')].iter()
+            .filter(|(bit, _)| bits & bit != 0)
+            .map(|(_, c)| *c)
+            .collect();
+        format!("{}={}", who, perms)
+    }).collect::<Vec<String>>().join(",")
+}
+
+// parses octal umasks like `022` and symbolic ones like `u=rwx,g-w,o=`
+pub fn parse_umask(spec: &str, current: libc::mode_t) -> Option<libc::mode_t> {
+    if spec.chars().all(|c| c.is_digit(8)) {
+        return libc::mode_t::from_str_radix(spec, 8).ok().filter(|mask| *mask <= 0o777);
+    }
+
+    let mut allowed = !current & 0o777;
+    for clause in spec.split(',') {
+        let op_index = clause.find(|c| "+-=".contains(c))?;
+        let (who, rest) = clause.split_at(op_index);
+        let op = rest.chars().next()?;
+
+        let mut who_bits = 0;
+        for c in who.chars() {
+            who_bits |= match c {
+                'u' => 0o700,
+                'g' => 0o070,
+                'o' => 0o007,
+                'a' => 0o777,
+                _ => return None
+            };
+        }
+        if who.is_empty() {
+            who_bits = 0o777;
+        }
+
+        let mut perm_bits = 0;
+        for c in rest[1..].chars() {
+            perm_bits |= match c {
+                'r' => 0o444,
+                'w' => 0o222,
+                'x' => 0o111,
+                _ => return None
+            };
+        }
+
+        let bits = who_bits & perm_bits;
+        match op {
+            '+' => allowed |= bits,
+            '-' => allowed &= !bits,
+            _ => allowed = (allowed & !who_bits) | bits
+        }
+    }
+
+    Some(!allowed & 0o777)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_octal_umask() {
+        assert_eq!(parse_umask("022", 0o077), Some(0o022));
+        assert_eq!(parse_umask("7", 0), Some(0o007));
+        assert_eq!(parse_umask("0777", 0), Some(0o777));
+        assert_eq!(parse_umask("1000", 0), None);
+        assert_eq!(parse_umask("089", 0), None);
+    }
+
+    #[test]
+    fn parse_symbolic_umask() {
+        assert_eq!(parse_umask("u=rwx,g=rx,o=", 0), Some(0o027));
+        assert_eq!(parse_umask("g-w", 0o002), Some(0o022));
+        assert_eq!(parse_umask("o+r", 0o077), Some(0o073));
+        assert_eq!(parse_umask("a=r", 0), Some(0o333));
+        assert_eq!(parse_umask("=rx", 0o022), Some(0o222));
+        assert_eq!(parse_umask("ug+w,o-rwx", 0o222), Some(0o007));
+        assert_eq!(parse_umask("u=z", 0), None);
+        assert_eq!(parse_umask("x=r", 0), None);
+        assert_eq!(parse_umask("rwx", 0), None);
+    }
+
+    #[test]
+    fn format_symbolic_umask() {
+        assert_eq!(format_umask_symbolic(0o022), "u=rwx,g=rx,o=rx");
+        assert_eq!(format_umask_symbolic(0o077), "u=rwx,g=,o=");
+        assert_eq!(format_umask_symbolic(0o777), "u=,g=,o=");
+
+        // what is shown reads back as the same mask
+        for mask in [0o000, 0o027, 0o751] {
+            assert_eq!(parse_umask(&format_umask_symbolic(mask), 0o777), Some(mask));
+        }
+    }
+}
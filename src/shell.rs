@@ -2,23 +2,50 @@ use crate::{
     token,
     command,
     evaluate::evaluate,
-    environment::Environment
+    environment::Environment,
+    signal
 };
 
 use std::{
     fmt, 
-    fs::File, 
-    io::Read,
+    fs::{
+        self,
+        File
+    }, 
+    ffi::CString,
+    io::{
+        self,
+        Read,
+        Seek,
+        SeekFrom,
+        Write
+    },
     env,
-    path::Path
+    os::unix::{
+        ffi::OsStrExt,
+        io::{
+            AsRawFd,
+            FromRawFd
+        }
+    },
+    path::{
+        Path,
+        PathBuf
+    }
 };
 
+const SYSTEM_CONFIG_DIR: &str = "/etc/sheesh";
+
+#[allow(clippy::enum_variant_names)]
 #[derive(Debug)]
 pub enum Error {
     ReadFile(String, String),
     SyntaxError(&'static str),
+    Incomplete(&'static str), // the input ends inside a block or string, or after `\`
     CommandNotFound(String),
     WrongNumOfArgs(&'static str, usize, usize),
+    ReadOnlyVariable(String),
+    Interrupted,
     EarlyExit(i32)
 }
 
@@ -27,8 +54,11 @@ impl fmt::Display for Error {
         match self {
             Self::ReadFile(path, err) => write!(f, "Error reading file \"{}\": {}", path, err),
             Self::SyntaxError(err) => write!(f, "Syntax error: {}", err),
+            Self::Incomplete(err) => write!(f, "Syntax error: {}", err),
             Self::CommandNotFound(exec) => write!(f, "Command not found: {}", exec),
             Self::WrongNumOfArgs(exec, expected, received) => write!(f, "{}: expect {} arguments, got {}", exec, expected, received),
+            Self::ReadOnlyVariable(name) => write!(f, "Cannot modify read-only variable `{}`", name),
+            Self::Interrupted => write!(f, "Interrupted"),
             Self::EarlyExit(code) => write!(f, "Process exited with code {}.", code)
         }
     }
@@ -45,7 +75,98 @@ pub fn execute(input: String, env: &mut Environment) -> Result<i32, Error> {
     evaluate(commands, env)
 }
 
-fn read_file(path: String) -> Result<String, Error> {
+// parses `input` without running it
+pub fn check_syntax(input: String) -> Result<(), Error> {
+    command::parse(token::tokenize(input)?).map(|_| ())
+}
+
+// whether more input is needed to complete `input`, like after `if` without `end`
+pub fn is_incomplete(input: &str) -> bool {
+    matches!(check_syntax(input.to_string()), Err(Error::Incomplete(_)))
+}
+
+// runs shell code with its output going to a temporary file and its errors to /dev/null
+pub fn capture_output(code: &str, env: &mut Environment) -> io::Result<(bool, String)> {
+    let mut file = private_temp_file()?;
+    let null = File::create("/dev/null")?;
+
+    io::stdout().flush()?;
+    let (saved_stdout, saved_stderr) = unsafe {
+        let saved = (libc::dup(libc::STDOUT_FILENO), libc::dup(libc::STDERR_FILENO));
+        libc::dup2(file.as_raw_fd(), libc::STDOUT_FILENO);
+        libc::dup2(null.as_raw_fd(), libc::STDERR_FILENO);
+        saved
+    };
+
+    let result = execute(format!("{}\n", code), env);
+    io::stdout().flush().ok();
+
+    unsafe {
+        libc::dup2(saved_stdout, libc::STDOUT_FILENO);
+        libc::dup2(saved_stderr, libc::STDERR_FILENO);
+        libc::close(saved_stdout);
+        libc::close(saved_stderr);
+    }
+
+    let mut output = String::new();
+    file.seek(SeekFrom::Start(0))?;
+    file.read_to_string(&mut output)?;
+    Ok((matches!(result, Ok(0)), output))
+}
+
+// a new file only this process can reach: created exclusively by `mkstemp`, which never
+// follows an existing name, and unlinked right away
+fn private_temp_file() -> io::Result<File> {
+    let template = env::temp_dir().join("sheesh-capture.XXXXXX");
+    let mut template = CString::new(template.as_os_str().as_bytes())
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?
+        .into_bytes_with_nul();
+
+    let fd = unsafe { libc::mkstemp(template.as_mut_ptr() as *mut libc::c_char) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    unsafe { libc::unlink(template.as_ptr() as *const libc::c_char) };
+    Ok(unsafe { File::from_raw_fd(fd) })
+}
+
+// runs the command registered with `trap` for a signal or the `EXIT` and `ERR` pseudo-signals
+pub fn run_trap(name: &str, env: &mut Environment) -> Result<(), Error> {
+    let source = match env.find_trap(name) {
+        Some(source) if !source.is_empty() && !env.is_running_trap() => source.clone(),
+        _ => return Ok(())
+    };
+
+    // a trap must not change `$status` for the code it interrupted
+    let status = env.find_var(&String::from("status")).map(|var| var.value()).unwrap_or_default();
+
+    env.set_running_trap(true);
+    let result = execute(source, env);
+    env.set_running_trap(false);
+    env.set_internal_var("status", status);
+
+    match result {
+        Err(Error::EarlyExit(code)) => Err(Error::EarlyExit(code)),
+        Err(err) => {
+            eprintln!("trap {}: {}", name, err);
+            Ok(())
+        }
+        Ok(_) => Ok(())
+    }
+}
+
+pub fn run_pending_traps(env: &mut Environment) -> Result<(), Error> {
+    for sig in signal::take_pending() {
+        if let Some(name) = signal::signal_abbrev(sig) {
+            run_trap(name, env)?;
+        }
+    }
+
+    Ok(())
+}
+
+pub fn read_file(path: String) -> Result<String, Error> {
     match File::open(path.clone()) {
         Ok(mut file) => {
             let mut buf = String::new();
@@ -68,12 +189,111 @@ pub fn run_script(path: String, env: &mut Environment) -> Result<i32, Error> {
     execute(input, env)
 }
 
-pub fn set_directory(path: &String) -> Result<i32, Error> {
+// keeps a new copy of the shell from loading the configuration
+pub const NO_CONFIG_VAR: &str = "SHEESH_NO_CONFIG";
+
+// runs the profile files of login shells, then `/etc/sheesh/config.sh`, the user's
+// `config.sh` and the `conf.d/*.sh` snippets in sorted order, skipping missing files
+pub fn load_config(env: &mut Environment) -> Result<(), Error> {
+    let system_dir = PathBuf::from(SYSTEM_CONFIG_DIR);
+    let user_dir = config_dir();
+    let mut files = Vec::new();
+
+    if env.is_login() {
+        files.push(system_dir.join("profile.sh"));
+        files.extend(user_dir.as_ref().map(|dir| dir.join("profile.sh")));
+    }
+
+    files.push(system_dir.join("config.sh"));
+    if let Some(dir) = &user_dir {
+        files.push(dir.join("config.sh"));
+
+        let mut snippets: Vec<PathBuf> = fs::read_dir(dir.join("conf.d"))
+            .map(|entries| entries.flatten().map(|entry| entry.path()).collect())
+            .unwrap_or_default();
+        snippets.retain(|file| file.extension().is_some_and(|ext| ext == "sh"));
+        snippets.sort();
+        files.extend(snippets);
+    }
+
+    for file in files.into_iter().filter(|file| file.is_file()) {
+        match run_script(file.to_string_lossy().to_string(), env) {
+            Err(Error::EarlyExit(code)) => return Err(Error::EarlyExit(code)),
+            Err(err) => eprintln!("sheesh: {}: {}", file.display(), err),
+            Ok(_) => ()
+        }
+    }
+
+    Ok(())
+}
+
+pub fn set_directory(path: &String, env: &mut Environment) -> Result<i32, Error> {
     match env::set_current_dir(Path::new(path)) {
         Err(err) => {
             eprintln!("cd: {}", err);
             Ok(1)
         }
-        Ok(_) => Ok(0)
+        Ok(_) => {
+            if let Ok(cwd) = env::current_dir() {
+                env.set_internal_var("PWD", cwd.to_string_lossy().to_string());
+            }
+            Ok(0)
+        }
     }
-}
\ No newline at end of file
+}
+
+// `$XDG_CONFIG_HOME/sheesh`, falling back to `~/.config/sheesh`
+pub fn config_dir() -> Option<PathBuf> {
+    match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => Some(PathBuf::from(dir).join("sheesh")),
+        _ => env::var_os("HOME").map(|home| PathBuf::from(home).join(".config").join("sheesh"))
+    }
+}
+
+// `$XDG_DATA_HOME/sheesh`, falling back to `~/.local/share/sheesh`
+pub fn data_dir() -> Option<PathBuf> {
+    match env::var_os("XDG_DATA_HOME") {
+        Some(dir) if !dir.is_empty() => Some(PathBuf::from(dir).join("sheesh")),
+        _ => env::var_os("HOME").map(|home| PathBuf::from(home).join(".local").join("share").join("sheesh"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn open_blocks_and_strings_are_incomplete() {
+        for input in [
+            "if true\n",
+            "if true\necho a\nelse\n",
+            "def f\nwhile true\nend\n",
+            "while true\n\n",
+            "echo 'abc\n",
+            "echo \"say \\\"hi\n",
+            "echo a \\\n",
+            "echo a\\\n"
+        ] {
+            assert!(is_incomplete(input), "`{}` is complete", input.escape_debug());
+        }
+    }
+
+    #[test]
+    fn finished_input_is_complete() {
+        for input in [
+            "echo a\n",
+            "if true\necho a\nend\n",
+            "def f\nwhile true\nend\nend\n",
+            "echo 'if'\n",
+            "echo a # if\n",
+            "echo a \\\nb\n",
+            "echo a |\n",
+            "echo a &&\n",
+            // errors other than missing input show up right away
+            "end\n",
+            "echo >\n"
+        ] {
+            assert!(!is_incomplete(input), "`{}` is incomplete", input.escape_debug());
+        }
+    }
+}
@@ -0,0 +1,183 @@
+use std::{
+    ffi::CStr,
+    mem,
+    sync::atomic::{
+        AtomicBool,
+        AtomicI32,
+        AtomicU64,
+        Ordering
+    }
+};
+
+pub const SIGNALS: [(&str, i32); 24] = [
+    ("HUP", libc::SIGHUP),
+    ("INT", libc::SIGINT),
+    ("QUIT", libc::SIGQUIT),
+    ("ILL", libc::SIGILL),
+    ("TRAP", libc::SIGTRAP),
+    ("ABRT", libc::SIGABRT),
+    ("BUS", libc::SIGBUS),
+    ("FPE", libc::SIGFPE),
+    ("KILL", libc::SIGKILL),
+    ("USR1", libc::SIGUSR1),
+    ("SEGV", libc::SIGSEGV),
+    ("USR2", libc::SIGUSR2),
+    ("PIPE", libc::SIGPIPE),
+    ("ALRM", libc::SIGALRM),
+    ("TERM", libc::SIGTERM),
+    ("CHLD", libc::SIGCHLD),
+    ("CONT", libc::SIGCONT),
+    ("STOP", libc::SIGSTOP),
+    ("TSTP", libc::SIGTSTP),
+    ("TTIN", libc::SIGTTIN),
+    ("TTOU", libc::SIGTTOU),
+    ("URG", libc::SIGURG),
+    ("XCPU", libc::SIGXCPU),
+    ("WINCH", libc::SIGWINCH),
+];
+
+// set by the SIGINT handler, cleared by whoever handles the interruption
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+// whether `init_interactive` installed its handlers
+static INTERACTIVE: AtomicBool = AtomicBool::new(false);
+
+// bitmask of trapped signals received since the traps were last run
+static PENDING: AtomicU64 = AtomicU64::new(0);
+
+// the foreground child sharing the shell's process group, if job control is disabled
+static FOREGROUND_PID: AtomicI32 = AtomicI32::new(0);
+
+// set by the SIGWINCH handler, so that the line editor can redraw
+static RESIZED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn noop_handler(_sig: libc::c_int) {}
+
+extern "C" fn resize_handler(_sig: libc::c_int) {
+    RESIZED.store(true, Ordering::SeqCst);
+}
+
+// A handler is used instead of `SIG_IGN`, because caught signals are reset to their
+// default disposition on exec, while ignored ones would be inherited by every child.
+pub fn install_noop_handler(sig: libc::c_int) {
+    unsafe {
+        let mut action: libc::sigaction = mem::zeroed();
+        action.sa_sigaction = noop_handler as *const () as libc::sighandler_t;
+        action.sa_flags = libc::SA_RESTART;
+        libc::sigemptyset(&mut action.sa_mask);
+        libc::sigaction(sig, &action, std::ptr::null_mut());
+    }
+}
+
+extern "C" fn trap_handler(sig: libc::c_int) {
+    PENDING.fetch_or(1 << sig, Ordering::SeqCst);
+}
+
+// keeps an interactive shell alive on SIGINT, SIGQUIT and SIGTSTP
+pub fn init_interactive() {
+    INTERACTIVE.store(true, Ordering::SeqCst);
+    install_noop_handler(libc::SIGQUIT);
+    install_noop_handler(libc::SIGTSTP);
+
+    // the editor waits for keys with poll(2), which a resize interrupts even with SA_RESTART
+    unsafe {
+        let mut action: libc::sigaction = mem::zeroed();
+        action.sa_sigaction = resize_handler as *const () as libc::sighandler_t;
+        action.sa_flags = libc::SA_RESTART;
+        libc::sigemptyset(&mut action.sa_mask);
+        libc::sigaction(libc::SIGWINCH, &action, std::ptr::null_mut());
+    }
+
+    let result = ctrlc::set_handler(|| {
+        INTERRUPTED.store(true, Ordering::SeqCst);
+        PENDING.fetch_or(1 << libc::SIGINT, Ordering::SeqCst);
+
+        let pid = FOREGROUND_PID.load(Ordering::SeqCst);
+        if pid > 0 {
+            unsafe { libc::kill(pid, libc::SIGINT) };
+        }
+    });
+
+    if let Err(err) = result {
+        eprintln!("sheesh: could not install SIGINT handler: {}", err);
+    }
+}
+
+pub fn interrupt() {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
+pub fn take_interrupted() -> bool {
+    INTERRUPTED.swap(false, Ordering::SeqCst)
+}
+
+pub fn set_foreground_pid(pid: Option<u32>) {
+    FOREGROUND_PID.store(pid.map_or(0, |pid| pid as i32), Ordering::SeqCst);
+}
+
+// the line editor reads Ctrl-C as a key, but `trap INT` should still see it
+pub fn record_interrupt() {
+    PENDING.fetch_or(1 << libc::SIGINT, Ordering::SeqCst);
+}
+
+pub fn take_resized() -> bool {
+    RESIZED.swap(false, Ordering::SeqCst)
+}
+
+// makes the shell record the signal for `trap`; an empty trap ignores the signal
+pub fn trap(sig: i32, ignore: bool) {
+    // the SIGINT handler of an interactive shell already records the signal
+    if sig == libc::SIGINT && INTERACTIVE.load(Ordering::SeqCst) {
+        return;
+    }
+
+    unsafe {
+        let mut action: libc::sigaction = mem::zeroed();
+        action.sa_sigaction = if ignore { libc::SIG_IGN } else { trap_handler as *const () as libc::sighandler_t };
+        action.sa_flags = libc::SA_RESTART;
+        libc::sigemptyset(&mut action.sa_mask);
+        libc::sigaction(sig, &action, std::ptr::null_mut());
+    }
+}
+
+// restores the disposition the shell had before `trap`
+pub fn untrap(sig: i32) {
+    let interactive = INTERACTIVE.load(Ordering::SeqCst);
+    match sig {
+        libc::SIGINT if interactive => (),
+        libc::SIGQUIT | libc::SIGTSTP if interactive => install_noop_handler(sig),
+        _ => unsafe { libc::signal(sig, libc::SIG_DFL); }
+    }
+}
+
+pub fn take_pending() -> Vec<i32> {
+    let pending = PENDING.swap(0, Ordering::SeqCst);
+    (1..64).filter(|sig| pending & (1 << sig) != 0).collect()
+}
+
+// accepts `INT`, `SIGINT` and `2`
+pub fn parse_signal(name: &str) -> Option<i32> {
+    if let Ok(sig) = name.parse::<i32>() {
+        return SIGNALS.iter().find(|(_, num)| *num == sig).map(|(_, num)| *num);
+    }
+
+    let name = name.to_uppercase();
+    let name = name.strip_prefix("SIG").unwrap_or(&name);
+    SIGNALS.iter().find(|(sig_name, _)| *sig_name == name).map(|(_, num)| *num)
+}
+
+pub fn signal_abbrev(sig: i32) -> Option<&'static str> {
+    SIGNALS.iter().find(|(_, num)| *num == sig).map(|(name, _)| *name)
+}
+
+pub fn signal_name(sig: i32) -> String {
+    unsafe {
+        let name = libc::strsignal(sig);
+        if name.is_null() {
+            format!("signal {}", sig)
+        }
+        else {
+            CStr::from_ptr(name).to_string_lossy().to_string()
+        }
+    }
+}
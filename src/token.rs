@@ -1,5 +1,6 @@
 use crate::shell::Error;
 
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Debug, PartialEq, Clone, Copy)]
 pub enum TokenKind {
     // any identifier
@@ -16,8 +17,11 @@ pub enum TokenKind {
     AND,       // &&
     PIPE,      // |
     OR,        // ||
-    INSTREAM,  // <
-    OUTSTREAM, // >
+    INSTREAM,  // <, 3<, <&3, <&-
+    OUTSTREAM, // >, 2>, >&1, 2>&-
+    APPEND,    // >>, 2>>
+
+    COMMENT, // # comment, only kept by `tokenize_partial`
 
     NEWLINE, // new line
     EOF // end of file
@@ -62,11 +66,10 @@ impl Token {
             '\n' => char_token!(NEWLINE, c),
             ';' => char_token!(SEMICOLON, c),
             '=' => char_token!(ASSIGN, c),
-            '>' => char_token!(OUTSTREAM, c),
-            '<' => char_token!(INSTREAM, c),
+            '<' | '>' => Ok(redirect_token(input, 0)),
 
             '&' => {
-                if let Some(next) = chars.next() && next == '&' {
+                if chars.next() == Some('&') {
                     Ok(Token { 
                         kind: TokenKind::AND, 
                         value: String::from("&&")
@@ -78,7 +81,7 @@ impl Token {
             }
 
             '|' => {
-                if let Some(next) = chars.next() && next == '|' {
+                if chars.next() == Some('|') {
                     Ok(Token { 
                         kind: TokenKind::OR, 
                         value: String::from("||")
@@ -90,14 +93,14 @@ impl Token {
             }
 
             '\'' | '\"' => {
-                let len = skip_until(&mut chars, |ch| ch == c);
-                if let Err(_) = len {
-                    return Err(Error::SyntaxError("Unterminated `'` string"))
-                }
+                let len = match string_len(chars.as_str(), c) {
+                    Some(len) => len,
+                    None => return Err(Error::Incomplete("Unterminated `'` string"))
+                };
 
                 Ok(Self {
                     kind: TokenKind::STRING,
-                    value: input[0..len.unwrap() + 2].to_string()
+                    value: input[0..len + 2].to_string()
                 })
             }
 
@@ -107,6 +110,19 @@ impl Token {
                     Err(v) => v
                 };
 
+                let mut len = len + c.len_utf8() - 1;
+
+                // a backslash ending the line continues it, it is not part of the word
+                if len > 0 && input[..len + 1].ends_with('\\') && is_line_end(&input[len + 1..]) {
+                    len -= 1;
+                }
+
+                // a file descriptor number directly in front of a redirection, like `2>`
+                let word = &input[0..len + 1];
+                if word.chars().all(|ch| ch.is_ascii_digit()) && input[len + 1..].starts_with(['<', '>']) {
+                    return Ok(redirect_token(input, len + 1));
+                }
+
                 Ok(Self {
                     kind: if c == '$' { 
                         TokenKind::VARIABLE 
@@ -136,21 +152,99 @@ impl Token {
     }
 }
 
+// reads a redirection operator starting at `start`, preceded by an optional fd number
+fn redirect_token(input: &str, start: usize) -> Token {
+    let rest = &input[start..];
+    let (kind, mut len) = if rest.starts_with(">>") {
+        (TokenKind::APPEND, 2)
+    } else if rest.starts_with('>') {
+        (TokenKind::OUTSTREAM, 1)
+    } else {
+        (TokenKind::INSTREAM, 1)
+    };
+
+    // duplicating (`>&2`) or closing (`<&-`) a file descriptor
+    if kind != TokenKind::APPEND && rest[len..].starts_with('&') {
+        let target = &rest[len + 1..];
+        let target_len = if target.starts_with('-') {
+            1
+        } else {
+            target.chars().take_while(|ch| ch.is_ascii_digit()).count()
+        };
+
+        if target_len > 0 {
+            len += 1 + target_len;
+        }
+    }
+
+    Token {
+        kind,
+        value: input[0..start + len].to_string()
+    }
+}
+
+// returns the length in bytes of the skipped characters
 fn skip_until<F>(chars: &mut std::str::Chars, cmp: F) -> Result<usize, usize> 
     where F: Fn(char) -> bool
 {
     let mut i = 0usize;
-    while !cmp(chars.peekable().next().ok_or(i)?) {
-        i += 1;
+    loop {
+        let ch = chars.peekable().next().ok_or(i)?;
+        if cmp(ch) {
+            break;
+        }
+        i += ch.len_utf8();
     }
 
     Ok(i)
 }
 
+// the length in bytes of a string's contents, up to the closing `quote`;
+// inside double quotes, `\"` and `\\` are escapes
+fn string_len(input: &str, quote: char) -> Option<usize> {
+    let mut chars = input.char_indices();
+    while let Some((i, c)) = chars.next() {
+        if c == quote {
+            return Some(i);
+        }
+        if c == '\\' && quote == '"' {
+            chars.next();
+        }
+    }
+    None
+}
+
+// the contents of a string token, with the escapes of double quotes resolved
+pub fn unquote(string: &str) -> String {
+    let mut chars = string.chars();
+    let quote = chars.next();
+    chars.next_back();
+
+    if quote != Some('"') {
+        return chars.collect();
+    }
+
+    let mut contents = String::new();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\\', Some(next)) if next == '"' || next == '\\' => {
+                contents.push(next);
+                chars.next();
+            }
+            _ => contents.push(c)
+        }
+    }
+    contents
+}
+
 fn is_whitespace(c: char) -> bool {
     " \t\r".contains(c)
 }
 
+fn is_line_end(input: &str) -> bool {
+    input.is_empty() || input.starts_with('\n')
+}
+
 fn skip_whitespace(input: &str) -> usize {
     match skip_until(&mut input.chars(), |ch| !is_whitespace(ch)) {
         Ok(v) => v,
@@ -159,27 +253,111 @@ fn skip_whitespace(input: &str) -> usize {
 }
 
 pub fn tokenize(input: String) -> Result<Vec<Token>, Error> {
+    Ok(scan(&input, false)?.into_iter().map(|(_, tok)| tok).collect())
+}
+
+// tokenizes input that is still being typed, along with the byte offset of each token.
+// Never fails: an unterminated string runs to the end of the input. Comments are kept.
+pub fn tokenize_partial(input: &str) -> Vec<(usize, Token)> {
+    scan(input, true).unwrap_or_default()
+}
+
+fn scan(input: &str, partial: bool) -> Result<Vec<(usize, Token)>, Error> {
     let mut tokens = Vec::new();
     let mut i = 0usize;
 
     while i < input.len() {
         i += skip_whitespace(&input[i..]);
-        if input.as_bytes()[i] as char == '#' {
-            i += match skip_until(&mut input.chars(), |c| c == '\n') {
+
+        // a backslash at the end of a line continues the command on the next one,
+        // only outside of strings and comments
+        if let Some(rest) = input[i..].strip_prefix('\\').filter(|rest| is_line_end(rest)) {
+            if rest.len() <= 1 && !partial {
+                return Err(Error::Incomplete("Expected another line after `\\`"));
+            }
+            i += 1 + rest.len().min(1);
+            continue;
+        }
+
+        if input[i..].starts_with('#') {
+            let len = match skip_until(&mut input[i..].chars(), |c| c == '\n') {
                 Ok(v) => v,
                 Err(v) => v
             };
+
+            if partial {
+                tokens.push((i, Token {
+                    kind: TokenKind::COMMENT,
+                    value: input[i..i + len].to_string()
+                }));
+            }
+            i += len;
         }
 
-        let tok = Token::get_token(&input[i..])?;
-        i += tok.len();
+        let tok = match Token::get_token(&input[i..]) {
+            Err(_) if partial => Token {
+                kind: TokenKind::STRING,
+                value: input[i..].to_string()
+            },
+            result => result?
+        };
 
-        tokens.push(tok.clone());
+        let len = tok.len();
+        let is_eof = tok.is_eof();
+        tokens.push((i, tok));
+        i += len;
 
-        if tok.is_eof() {
+        if is_eof {
             break;
         }
     }
 
     Ok(tokens)
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use TokenKind::*;
+
+    fn assert_tokens(input: &str, expected: &[(TokenKind, &str)]) {
+        let tokens: Vec<(TokenKind, String)> = tokenize(input.to_string()).unwrap().into_iter()
+            .filter(|tok| !matches!(tok.kind(), NEWLINE | EOF))
+            .map(|tok| (tok.kind(), tok.value().clone()))
+            .collect();
+        let expected: Vec<(TokenKind, String)> = expected.iter().map(|(kind, value)| (*kind, value.to_string())).collect();
+        assert_eq!(tokens, expected, "tokens of `{}`", input);
+    }
+
+    #[test]
+    fn redirection_tokens() {
+        assert_tokens("cat <in >out 2>>log 2>&1 >&- 3<&0 >>'with space'", &[
+            (GENERIC, "cat"),
+            (INSTREAM, "<"), (GENERIC, "in"),
+            (OUTSTREAM, ">"), (GENERIC, "out"),
+            (APPEND, "2>>"), (GENERIC, "log"),
+            (OUTSTREAM, "2>&1"),
+            (OUTSTREAM, ">&-"),
+            (INSTREAM, "3<&0"),
+            (APPEND, ">>"), (STRING, "'with space'")
+        ]);
+    }
+
+    #[test]
+    fn only_digits_right_before_the_operator_make_a_file_descriptor() {
+        assert_tokens("echo a2>b", &[(GENERIC, "echo"), (GENERIC, "a2"), (OUTSTREAM, ">"), (GENERIC, "b")]);
+        assert_tokens("echo 2 >b", &[(GENERIC, "echo"), (GENERIC, "2"), (OUTSTREAM, ">"), (GENERIC, "b")]);
+
+        // `>&` without a number or `-` is a plain redirection followed by `&`
+        assert_tokens("echo >&x", &[(GENERIC, "echo"), (OUTSTREAM, ">"), (BGPROCESS, "&"), (GENERIC, "x")]);
+    }
+
+    #[test]
+    fn backslash_at_the_line_end_continues_the_command() {
+        assert_tokens("echo a \\\n  b\n", &[(GENERIC, "echo"), (GENERIC, "a"), (GENERIC, "b")]);
+        assert_tokens("echo a\\\nb\n", &[(GENERIC, "echo"), (GENERIC, "a"), (GENERIC, "b")]);
+        assert_tokens("echo a\\b\n", &[(GENERIC, "echo"), (GENERIC, "a\\b")]);
+        assert_tokens("echo 'a\\\nb'\n", &[(GENERIC, "echo"), (STRING, "'a\\\nb'")]);
+        assert!(matches!(tokenize(String::from("echo a \\\n")), Err(Error::Incomplete(_))));
+    }
+}
@@ -0,0 +1,187 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io,
+    os::unix::io::AsRawFd,
+    path::PathBuf,
+    process,
+    time::SystemTime
+};
+
+use crate::shell;
+
+const FILE_NAME: &str = "universal_variables";
+
+// fish-style universal variables, shared by all running sheesh instances through
+// a file in `$XDG_CONFIG_HOME/sheesh/`
+pub struct UniversalVars {
+    path: Option<PathBuf>,
+    modified: Option<SystemTime>,
+    values: HashMap<String, String>
+}
+
+impl UniversalVars {
+    pub fn load() -> Self {
+        let mut vars = Self {
+            path: shell::config_dir().map(|dir| dir.join(FILE_NAME)),
+            modified: None,
+            values: HashMap::new()
+        };
+        vars.reload();
+        vars
+    }
+
+    pub fn values(&self) -> &HashMap<String, String> {
+        &self.values
+    }
+
+    fn file_modified(&self) -> Option<SystemTime> {
+        fs::metadata(self.path.as_ref()?).and_then(|meta| meta.modified()).ok()
+    }
+
+    // re-reads the file if another instance changed it; returns true if it did
+    pub fn reload(&mut self) -> bool {
+        let modified = self.file_modified();
+        if modified == self.modified {
+            return false;
+        }
+
+        self.modified = modified;
+        self.values = self.read_file();
+        true
+    }
+
+    fn read_file(&self) -> HashMap<String, String> {
+        let mut values = HashMap::new();
+        let contents = match &self.path {
+            Some(path) => fs::read_to_string(path).unwrap_or_default(),
+            None => return values
+        };
+
+        for line in contents.lines() {
+            if let Some((name, value)) = line.split_once('=') {
+                values.insert(name.to_string(), unescape(value));
+            }
+        }
+
+        values
+    }
+
+    pub fn set(&mut self, name: String, value: String) -> io::Result<()> {
+        self.update(|values| { values.insert(name, value); })
+    }
+
+    pub fn remove(&mut self, name: &String) -> io::Result<bool> {
+        let mut removed = false;
+        self.update(|values| removed = values.remove(name).is_some())?;
+        Ok(removed)
+    }
+
+    // merges the change into the current file contents, so that changes made by other
+    // instances in the meantime are not lost
+    fn update<F>(&mut self, change: F) -> io::Result<()>
+        where F: FnOnce(&mut HashMap<String, String>)
+    {
+        let path = match &self.path {
+            Some(path) => path.clone(),
+            None => return Err(io::Error::new(io::ErrorKind::NotFound, "neither $XDG_CONFIG_HOME nor $HOME is set"))
+        };
+
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        // other instances wait until this one has written its change
+        let lock = fs::OpenOptions::new().create(true).write(true).truncate(false).open(path.with_extension("lock"))?;
+        if unsafe { libc::flock(lock.as_raw_fd(), libc::LOCK_EX) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut values = self.read_file();
+        change(&mut values);
+
+        let mut names: Vec<&String> = values.keys().collect();
+        names.sort();
+
+        let mut contents = String::new();
+        for name in names {
+            contents.push_str(&format!("{}={}\n", name, escape(&values[name])));
+        }
+
+        // write to a temporary file first, so readers never see a half-written file
+        let tmp_path = path.with_extension(format!("tmp.{}", process::id()));
+        fs::write(&tmp_path, contents)?;
+        fs::rename(&tmp_path, &path)?;
+
+        self.modified = self.file_modified();
+        self.values = values;
+        Ok(()) // the lock is released when the file is closed
+    }
+}
+
+// also used by the history file, which separates its fields with tabs
+pub fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('\n', "\\n").replace('\t', "\\t")
+}
+
+pub fn unescape(value: &str) -> String {
+    let mut result = String::new();
+    let mut chars = value.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('n') => result.push('\n'),
+            Some('t') => result.push('\t'),
+            Some(c) => result.push(c),
+            None => result.push('\\')
+        }
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // two instances sharing a file in a directory of their own
+    fn instances(name: &str) -> (UniversalVars, UniversalVars) {
+        let dir = std::env::temp_dir().join(format!("sheesh-universal-{}-{}", process::id(), name));
+        fs::remove_dir_all(&dir).ok();
+
+        let path = Some(dir.join(FILE_NAME));
+        let first = UniversalVars { path: path.clone(), modified: None, values: HashMap::new() };
+        let second = UniversalVars { path, modified: None, values: HashMap::new() };
+        (first, second)
+    }
+
+    #[test]
+    fn escaped_values_read_back_unchanged() {
+        for value in ["plain", "two\nlines", "a\ttab", "back\\slash", "\\n literally", "trailing\\"] {
+            assert_eq!(unescape(&escape(value)), value);
+            assert!(!escape(value).contains(['\n', '\t']));
+        }
+    }
+
+    #[test]
+    fn update_keeps_changes_of_other_instances() {
+        let (mut first, mut second) = instances("merge");
+        first.set(String::from("a"), String::from("1")).unwrap();
+        second.set(String::from("b"), String::from("two\nlines")).unwrap();
+
+        assert_eq!(second.values().get("a").map(String::as_str), Some("1"));
+        assert_eq!(first.read_file().get("b").map(String::as_str), Some("two\nlines"));
+
+        assert!(first.remove(&String::from("a")).unwrap());
+        assert!(!first.remove(&String::from("a")).unwrap());
+        assert_eq!(first.values().len(), 1);
+        assert_eq!(second.read_file(), first.values().clone());
+
+        fs::remove_dir_all(first.path.unwrap().parent().unwrap()).ok();
+    }
+}